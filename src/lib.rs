@@ -1,3 +1,4 @@
+use wlroots_sys::wayland_server::protocol::wl_output;
 use wlroots_sys::wayland_server::protocol::wl_seat::Capability;
 use wlroots_sys::wayland_sys::server::signal::wl_signal_add;
 use wlroots_sys::wlr_log_importance::*;
@@ -12,6 +13,11 @@ use std::collections::VecDeque;
 
 #[macro_use]
 mod macros;
+#[cfg(feature = "stream")]
+mod stream;
+
+#[cfg(feature = "stream")]
+pub use stream::EventStream;
 
 #[repr(C)]
 pub struct Server {
@@ -24,13 +30,37 @@ pub struct Server {
     cursor_mgr: *mut wlr_xcursor_manager,
     seat: *mut wlr_seat,
     output_layout: *mut wlr_output_layout,
+    output_manager: *mut wlr_output_manager_v1,
+
+    socket_name: Option<String>,
+
+    cursor_image: Option<std::ffi::CString>,
+    /// Set while the cursor shows a surface of the focused client instead
+    /// of `cursor_image`
+    client_cursor: std::cell::Cell<bool>,
+    grab: Option<Grab>,
+
+    keymap_config: KeymapConfig,
+    repeat_rate: i32,
+    repeat_delay: i32,
 
-    socket_name: String,
+    mode_policy: Box<dyn FnMut(&Output) -> Option<Mode>>,
 
     outputs: Arena<Pin<Box<Output>>>,
     views: Arena<Pin<Box<View>>>,
-
+    keyboards: Arena<Pin<Box<Keyboard>>>,
+    input_devices: Arena<Pin<Box<InputDevice>>>,
+    output_configs: Arena<PendingOutputConfig>,
+    fd_sources: Arena<Pin<Box<FdSource>>>,
+    timers: Arena<Pin<Box<Timer>>>,
+    idles: Arena<Pin<Box<Idle>>>,
+
+    dead_outputs: Vec<Pin<Box<Output>>>,
     dead_views: Vec<Pin<Box<View>>>,
+    dead_keyboards: Vec<Pin<Box<Keyboard>>>,
+    dead_input_devices: Vec<Pin<Box<InputDevice>>>,
+    dead_idles: Vec<Pin<Box<Idle>>>,
+    dead_fd_sources: Vec<Pin<Box<FdSource>>>,
 
     event_queue: VecDeque<Event>,
 
@@ -43,13 +73,21 @@ pub struct Server {
     cursor_axis_listener: wl_listener,
     cursor_frame_listener: wl_listener,
     seat_request_set_cursor_listener: wl_listener,
+    output_layout_change_listener: wl_listener,
+    output_manager_apply_listener: wl_listener,
+    output_manager_test_listener: wl_listener,
 
     #[allow(dead_code)]
     unpin: PhantomPinned,
 }
 
 impl Server {
-    pub fn new() -> Result<Pin<Box<Server>>, &'static str> {
+    /// Creates a server with the backend picked by wlroots from the environment.
+    /// Use `ServerBuilder` to choose it explicitly.
+    pub fn new() -> Result<Pin<Box<Server>>, ServerError> {
+        ServerBuilder::new().build()
+    }
+    fn from_builder(builder: &ServerBuilder) -> Result<Pin<Box<Server>>, ServerError> {
         let mut c = Box::pin(Server {
             unpin: PhantomPinned,
             display: std::ptr::null_mut(),
@@ -60,13 +98,36 @@ impl Server {
             cursor_mgr: std::ptr::null_mut(),
             seat: std::ptr::null_mut(),
             output_layout: std::ptr::null_mut(),
+            output_manager: std::ptr::null_mut(),
+
+            socket_name: None,
 
-            socket_name: String::new(),
+            // what clients expect when they don't set a cursor themselves
+            cursor_image: Some(std::ffi::CString::new("left_ptr").unwrap()),
+            client_cursor: std::cell::Cell::new(false),
+            grab: None,
+
+            keymap_config: KeymapConfig::default(),
+            repeat_rate: 25,
+            repeat_delay: 600,
+
+            mode_policy: Box::new(preferred_mode),
 
             outputs: Arena::new(),
             views: Arena::new(),
-
+            keyboards: Arena::new(),
+            input_devices: Arena::new(),
+            output_configs: Arena::new(),
+            fd_sources: Arena::new(),
+            timers: Arena::new(),
+            idles: Arena::new(),
+
+            dead_outputs: Vec::new(),
             dead_views: Vec::new(),
+            dead_keyboards: Vec::new(),
+            dead_input_devices: Vec::new(),
+            dead_idles: Vec::new(),
+            dead_fd_sources: Vec::new(),
 
             event_queue: VecDeque::new(),
 
@@ -79,29 +140,75 @@ impl Server {
             cursor_axis_listener: unsafe { std::mem::zeroed() },
             cursor_frame_listener: unsafe { std::mem::zeroed() },
             seat_request_set_cursor_listener: unsafe { std::mem::zeroed() },
+            output_layout_change_listener: unsafe { std::mem::zeroed() },
+            output_manager_apply_listener: unsafe { std::mem::zeroed() },
+            output_manager_test_listener: unsafe { std::mem::zeroed() },
         });
         unsafe {
+            // On error, `c` is dropped and whatever was created is destroyed
             let ctx = c.as_mut().get_unchecked_mut();
             ctx.display =
                 ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_create,) as *mut wl_display;
-            ctx.backend = wlr_backend_autocreate(ctx.display, None);
+            if ctx.display.is_null() {
+                return Err(ServerError::DisplayCreation);
+            }
+            ctx.backend = builder.create_backend(ctx.display, &builder.backend);
+            if ctx.backend.is_null() {
+                return Err(ServerError::Backend);
+            }
             ctx.renderer = wlr_backend_get_renderer(ctx.backend);
-            wlr_renderer_init_wl_display(ctx.renderer, ctx.display);
-            wlr_compositor_create(ctx.display, ctx.renderer);
-            wlr_data_device_manager_create(ctx.display);
+            if ctx.renderer.is_null() || !wlr_renderer_init_wl_display(ctx.renderer, ctx.display) {
+                return Err(ServerError::Renderer);
+            }
+            if wlr_compositor_create(ctx.display, ctx.renderer).is_null() {
+                return Err(ServerError::Global("wl_compositor"));
+            }
+            if wlr_data_device_manager_create(ctx.display).is_null() {
+                return Err(ServerError::Global("wl_data_device_manager"));
+            }
 
             ctx.output_layout = wlr_output_layout_create();
+            if ctx.output_layout.is_null() {
+                return Err(ServerError::OutputLayout);
+            }
+
+            connect_listener!(ctx, output_layout, change);
+
+            ctx.output_manager = wlr_output_manager_v1_create(ctx.display);
+            if ctx.output_manager.is_null() {
+                return Err(ServerError::Global("zwlr_output_manager_v1"));
+            }
+
+            connect_listener!(ctx, output_manager, apply);
+            connect_listener!(ctx, output_manager, test);
+
+            // Follows the layout on its own: outputs added, moved, rescaled or
+            // removed from `output_layout` are sent to the clients. The global
+            // is owned by the display, so it is not kept around.
+            let xdg_output_manager = wlr_xdg_output_manager_v1_create(ctx.display, ctx.output_layout);
+            if xdg_output_manager.is_null() {
+                return Err(ServerError::Global("zxdg_output_manager_v1"));
+            }
 
             connect_listener!(ctx, backend, new_output);
             connect_listener!(ctx, backend, new_input);
 
             ctx.xdg_shell = wlr_xdg_shell_create(ctx.display);
+            if ctx.xdg_shell.is_null() {
+                return Err(ServerError::Global("xdg_wm_base"));
+            }
 
             connect_listener!(ctx, xdg_shell, new_surface);
 
             ctx.cursor = wlr_cursor_create();
+            if ctx.cursor.is_null() {
+                return Err(ServerError::Cursor);
+            }
             wlr_cursor_attach_output_layout(ctx.cursor, ctx.output_layout);
             ctx.cursor_mgr = wlr_xcursor_manager_create(std::ptr::null(), 24);
+            if ctx.cursor_mgr.is_null() {
+                return Err(ServerError::Cursor);
+            }
             wlr_xcursor_manager_load(ctx.cursor_mgr, 1.0);
 
             connect_listener!(ctx, cursor, motion);
@@ -111,48 +218,337 @@ impl Server {
             connect_listener!(ctx, cursor, frame);
 
             ctx.seat = wlr_seat_create(ctx.display, b"seat0\0".as_ptr() as *const _);
+            if ctx.seat.is_null() {
+                return Err(ServerError::Seat);
+            }
 
             connect_listener!(ctx, seat, request_set_cursor);
 
-            let socket_name_ptr = ffi_dispatch!(
-                WAYLAND_SERVER_HANDLE,
-                wl_display_add_socket_auto,
-                ctx.display as *mut _
-            );
-            if socket_name_ptr.is_null() {
-                return Err("cannot create socket");
+            match &builder.socket {
+                SocketKind::Auto => {
+                    let socket_name_ptr = ffi_dispatch!(
+                        WAYLAND_SERVER_HANDLE,
+                        wl_display_add_socket_auto,
+                        ctx.display as *mut _
+                    );
+                    if socket_name_ptr.is_null() {
+                        return Err(ServerError::Socket);
+                    }
+                    let socket_name_cstr = std::ffi::CStr::from_ptr(socket_name_ptr);
+                    ctx.socket_name = Some(
+                        socket_name_cstr
+                            .to_str()
+                            .expect("wayland socket name is not utf8")
+                            .to_owned(),
+                    );
+                }
+                SocketKind::Name(name) => {
+                    let cname = std::ffi::CString::new(name.as_str()).map_err(|_| ServerError::Socket)?;
+                    let ret = ffi_dispatch!(
+                        WAYLAND_SERVER_HANDLE,
+                        wl_display_add_socket,
+                        ctx.display as *mut _,
+                        cname.as_ptr()
+                    );
+                    if ret != 0 {
+                        return Err(ServerError::Socket);
+                    }
+                    ctx.socket_name = Some(name.clone());
+                }
+                SocketKind::Fd(fd) => {
+                    let ret = ffi_dispatch!(
+                        WAYLAND_SERVER_HANDLE,
+                        wl_display_add_socket_fd,
+                        ctx.display as *mut _,
+                        *fd
+                    );
+                    if ret != 0 {
+                        return Err(ServerError::Socket);
+                    }
+                }
+            }
+            if builder.set_wayland_display {
+                if let Some(name) = &ctx.socket_name {
+                    std::env::set_var("WAYLAND_DISPLAY", name);
+                }
             }
-            let socket_name_cstr = std::ffi::CStr::from_ptr(socket_name_ptr);
-            ctx.socket_name = socket_name_cstr
-                .to_str()
-                .expect("wayland socket name is not utf8")
-                .to_owned();
 
             if !wlr_backend_start(ctx.backend) {
-                return Err("cannot start backend");
+                return Err(ServerError::BackendStart);
             }
         }
         Ok(c)
     }
 
-    pub fn poll_events(mut self: Pin<&mut Self>) -> impl Iterator<Item = Event> {
+    /// The name of the socket clients connect to, i.e. their WAYLAND_DISPLAY.
+    /// `None` if the server listens on a socket fd given to `ServerBuilder::socket_fd`.
+    pub fn socket_name(&self) -> Option<&str> {
+        self.socket_name.as_ref().map(|s| s.as_str())
+    }
+    /// Runs `command` with `sh -c`, in a new session and with WAYLAND_DISPLAY
+    /// pointing to this server. The process is double-forked, so it is reparented
+    /// to init and never left as a zombie.
+    /// Returns the pid of the shell, which `exec`s simple commands, so it is
+    /// usually the pid of the client too (see `View::pid`).
+    /// ynwm doesn't run Xwayland, so DISPLAY is inherited as is.
+    pub fn spawn(&self, command: &str) -> std::io::Result<pid_t> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStringExt;
+        let sh = CString::new("/bin/sh").unwrap();
+        let flag = CString::new("-c").unwrap();
+        let command = CString::new(command)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let argv = [sh.as_ptr(), flag.as_ptr(), command.as_ptr(), std::ptr::null()];
+        let mut env: Vec<CString> = std::env::vars_os()
+            .filter(|(key, _)| self.socket_name.is_none() || key.as_os_str() != "WAYLAND_DISPLAY")
+            .filter_map(|(key, value)| {
+                let mut var = key.into_vec();
+                var.push(b'=');
+                var.extend(value.into_vec());
+                CString::new(var).ok()
+            })
+            .collect();
+        if let Some(name) = &self.socket_name {
+            env.push(CString::new(format!("WAYLAND_DISPLAY={}", name)).expect("null byte inside socket name"));
+        }
+        let mut envp: Vec<*const libc::c_char> = env.iter().map(|var| var.as_ptr()).collect();
+        envp.push(std::ptr::null());
+        let pid_size = std::mem::size_of::<pid_t>();
+        // Only async-signal-safe calls between fork and exec: everything is
+        // allocated above
+        unsafe {
+            let mut fds = [0; 2];
+            if libc::pipe(fds.as_mut_ptr()) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let child = libc::fork();
+            if child < 0 {
+                let err = std::io::Error::last_os_error();
+                libc::close(fds[0]);
+                libc::close(fds[1]);
+                return Err(err);
+            }
+            if child == 0 {
+                libc::close(fds[0]);
+                libc::setsid();
+                let grandchild = libc::fork();
+                if grandchild == 0 {
+                    libc::close(fds[1]);
+                    // Handlers are reset by exec, but the mask and the ignored
+                    // signals (e.g. SIGPIPE in Rust programs) are inherited
+                    let mut set: libc::sigset_t = std::mem::zeroed();
+                    libc::sigemptyset(&mut set);
+                    libc::sigprocmask(libc::SIG_SETMASK, &set, std::ptr::null_mut());
+                    for sig in 1..NSIG {
+                        libc::signal(sig, libc::SIG_DFL);
+                    }
+                    libc::execve(sh.as_ptr(), argv.as_ptr(), envp.as_ptr());
+                    libc::_exit(127);
+                }
+                libc::write(fds[1], &grandchild as *const _ as *const libc::c_void, pid_size);
+                libc::_exit(0);
+            }
+            libc::close(fds[1]);
+            let mut pid: pid_t = -1;
+            let n = loop {
+                let n = libc::read(fds[0], &mut pid as *mut _ as *mut libc::c_void, pid_size);
+                if n >= 0 || std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+                    break n;
+                }
+            };
+            libc::close(fds[0]);
+            while libc::waitpid(child, std::ptr::null_mut(), 0) < 0
+                && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+            {}
+            if n != pid_size as isize || pid < 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "cannot fork client process",
+                ));
+            }
+            Ok(pid)
+        }
+    }
+    /// The views whose client has the given pid.
+    pub fn views_for_pid<'a>(&'a self, pid: pid_t) -> impl Iterator<Item = Index> + 'a {
+        self.views
+            .iter()
+            .filter(move |(_, v)| v.pid() == Some(pid))
+            .map(|(idx, _)| idx)
+    }
+    /// Waits for events and returns them.
+    pub fn poll_events(self: Pin<&mut Self>) -> impl Iterator<Item = Event> {
+        self.dispatch(None)
+    }
+    /// Like `poll_events`, but waits at most `timeout`. A zero timeout
+    /// returns right away with the events that are already pending.
+    pub fn dispatch(mut self: Pin<&mut Self>, timeout: Option<std::time::Duration>) -> impl Iterator<Item = Event> {
+        let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::max_value() as u128) as i32);
         unsafe {
             let ctx = self.as_mut().get_unchecked_mut();
+            ctx.dead_outputs.clear();
             ctx.dead_views.clear();
-            let el = ffi_dispatch!(
+            ctx.dead_keyboards.clear();
+            ctx.dead_input_devices.clear();
+            ctx.dead_idles.clear();
+            ctx.dead_fd_sources.clear();
+            let el = ctx.event_loop();
+            ctx.flush_clients();
+            // Don't sleep if idle callbacks, or calls made since the last
+            // dispatch, already have events to return
+            ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_loop_dispatch_idle, el);
+            let timeout = if ctx.event_queue.is_empty() { timeout } else { 0 };
+            ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_loop_dispatch, el, timeout);
+            let mut events = VecDeque::new();
+            std::mem::swap(&mut events, &mut ctx.event_queue);
+            events.into_iter()
+        }
+    }
+    /// Sends the buffered messages to the clients. `dispatch` does it before
+    /// waiting, a loop that waits on `event_loop_fd` has to call it too.
+    pub fn flush_clients(&self) {
+        unsafe {
+            ffi_dispatch!(
+                WAYLAND_SERVER_HANDLE,
+                wl_display_flush_clients,
+                self.display as *mut _
+            );
+        }
+    }
+    /// The fd of the wayland event loop. It becomes readable when there is
+    /// something to dispatch, so it can be polled by another event loop that
+    /// then calls `dispatch` with a zero timeout.
+    pub fn event_loop_fd(&self) -> std::os::unix::io::RawFd {
+        unsafe { ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_loop_get_fd, self.event_loop()) }
+    }
+    fn event_loop(&self) -> *mut wayland_sys::server::wl_event_loop {
+        unsafe {
+            ffi_dispatch!(
                 WAYLAND_SERVER_HANDLE,
                 wl_display_get_event_loop,
-                ctx.display as *mut _
-            ) as *mut wayland_sys::server::wl_event_loop;
+                self.display as *mut _
+            ) as *mut wayland_sys::server::wl_event_loop
+        }
+    }
+    /// Watches `fd`: `Event::FdReadable` is emitted every time it becomes readable,
+    /// until `remove_fd` is called. The fd is not closed by the server.
+    /// On hang up or error `Event::FdClosed` is emitted and the fd is not
+    /// watched anymore.
+    pub fn add_fd(self: Pin<&mut Self>, fd: std::os::unix::io::RawFd) -> std::io::Result<FdId> {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let mut f = Box::pin(FdSource {
+            server: ctx as *mut _,
+            source: std::ptr::null_mut(),
+            fd,
+        });
+        unsafe {
+            let f_ctx = f.as_mut().get_unchecked_mut();
+            f_ctx.source = ffi_dispatch!(
+                WAYLAND_SERVER_HANDLE,
+                wl_event_loop_add_fd,
+                ctx.event_loop(),
+                fd,
+                WL_EVENT_READABLE,
+                FdSource::callback,
+                f_ctx as *mut _ as *mut _
+            );
+            if f_ctx.source.is_null() {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(ctx.fd_sources.insert(f))
+    }
+    pub fn remove_fd(self: Pin<&mut Self>, id: FdId) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        if let Some(f) = ctx.fd_sources.remove(id) {
+            unsafe {
+                ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_source_remove, f.source);
+            }
+        }
+    }
+    /// Emits `Event::Timer` once, after `timeout`. The timer can then be
+    /// armed again with `rearm_timer`, until `cancel_timer` is called.
+    pub fn add_timer(self: Pin<&mut Self>, timeout: std::time::Duration) -> std::io::Result<TimerId> {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let mut t = Box::pin(Timer {
+            server: ctx as *mut _,
+            source: std::ptr::null_mut(),
+        });
+        unsafe {
+            let t_ctx = t.as_mut().get_unchecked_mut();
+            t_ctx.source = ffi_dispatch!(
+                WAYLAND_SERVER_HANDLE,
+                wl_event_loop_add_timer,
+                ctx.event_loop(),
+                Timer::callback,
+                t_ctx as *mut _ as *mut _
+            );
+            if t_ctx.source.is_null() {
+                return Err(std::io::Error::last_os_error());
+            }
             ffi_dispatch!(
                 WAYLAND_SERVER_HANDLE,
-                wl_display_flush_clients,
-                ctx.display as *mut _
+                wl_event_source_timer_update,
+                t_ctx.source,
+                duration_to_timer_ms(timeout)
             );
-            ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_loop_dispatch, el, -1);
-            let mut events = VecDeque::new();
-            std::mem::swap(&mut events, &mut ctx.event_queue);
-            events.into_iter()
+        }
+        Ok(ctx.timers.insert(t))
+    }
+    /// Sets the timer to fire after `timeout`, whether it already fired or not.
+    /// Returns false if the timer was cancelled.
+    pub fn rearm_timer(&self, id: TimerId, timeout: std::time::Duration) -> bool {
+        match self.timers.get(id) {
+            Some(t) => unsafe {
+                ffi_dispatch!(
+                    WAYLAND_SERVER_HANDLE,
+                    wl_event_source_timer_update,
+                    t.source,
+                    duration_to_timer_ms(timeout)
+                );
+                true
+            },
+            None => false,
+        }
+    }
+    pub fn cancel_timer(self: Pin<&mut Self>, id: TimerId) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        if let Some(t) = ctx.timers.remove(id) {
+            unsafe {
+                ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_source_remove, t.source);
+            }
+        }
+    }
+    /// Emits `Event::Idle` once, the next time the event loop has nothing else
+    /// to do, i.e. after the events that are already pending.
+    pub fn add_idle(self: Pin<&mut Self>) -> std::io::Result<IdleId> {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let mut i = Box::pin(Idle {
+            server: ctx as *mut _,
+            source: std::ptr::null_mut(),
+        });
+        unsafe {
+            let i_ctx = i.as_mut().get_unchecked_mut();
+            i_ctx.source = ffi_dispatch!(
+                WAYLAND_SERVER_HANDLE,
+                wl_event_loop_add_idle,
+                ctx.event_loop(),
+                Idle::callback,
+                i_ctx as *mut _ as *mut _
+            );
+            if i_ctx.source.is_null() {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(ctx.idles.insert(i))
+    }
+    /// Cancels an idle callback that didn't run yet.
+    pub fn cancel_idle(self: Pin<&mut Self>, id: IdleId) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        if let Some(i) = ctx.idles.remove(id) {
+            unsafe {
+                ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_source_remove, i.source);
+            }
         }
     }
     pub fn get_output<'a>(&'a self, idx: Index) -> &'a Output {
@@ -162,6 +558,12 @@ impl Server {
         let ctx = unsafe { self.get_unchecked_mut() };
         ctx.outputs[idx].as_mut()
     }
+    /// Like `get_output_mut`, but returns `None` if the output was destroyed,
+    /// e.g. for events queued before its `OutputDestroy`.
+    pub fn try_get_output_mut<'a>(self: Pin<&'a mut Self>, idx: Index) -> Option<Pin<&'a mut Output>> {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        ctx.outputs.get_mut(idx).map(|o| o.as_mut())
+    }
     pub fn get_view<'a>(&'a self, idx: Index) -> &'a View {
         self.views[idx].as_ref().get_ref()
     }
@@ -169,24 +571,300 @@ impl Server {
         let ctx = unsafe { self.get_unchecked_mut() };
         ctx.views[idx].as_mut()
     }
+    pub fn get_keyboard<'a>(&'a self, idx: Index) -> &'a Keyboard {
+        self.keyboards[idx].as_ref().get_ref()
+    }
+    pub fn get_keyboard_mut<'a>(self: Pin<&'a mut Self>, idx: Index) -> Pin<&'a mut Keyboard> {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        ctx.keyboards[idx].as_mut()
+    }
+    pub fn get_input_device<'a>(&'a self, idx: Index) -> &'a InputDevice {
+        self.input_devices[idx].as_ref().get_ref()
+    }
+    pub fn input_devices<'a>(&'a self) -> impl Iterator<Item = (Index, &'a InputDevice)> {
+        self.input_devices
+            .iter()
+            .map(|(idx, d)| (idx, d.as_ref().get_ref()))
+    }
     pub fn cursor_move(self: Pin<&mut Self>, delta_x: f64, delta_y: f64) {
         let ctx = unsafe { self.get_unchecked_mut() };
         unsafe {
             wlr_cursor_move(ctx.cursor, std::ptr::null_mut(), delta_x, delta_y);
         }
+        ctx.update_grab();
     }
     pub fn cursor_move_absolute(self: Pin<&mut Self>, x: f64, y: f64) {
         let ctx = unsafe { self.get_unchecked_mut() };
         unsafe {
             wlr_cursor_warp_absolute(ctx.cursor, std::ptr::null_mut(), x, y);
         }
+        ctx.update_grab();
+    }
+    /// Starts moving `view` with the cursor, from its `View::position`.
+    /// Until `button` is released, cursor motion updates the position and
+    /// emits `ViewGeometryChanged`. Views that are gone are ignored.
+    pub fn begin_move(self: Pin<&mut Self>, view: Index, button: u32) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        ctx.begin_grab(view, button, GrabMode::Move);
+    }
+    /// Starts resizing `view` with the cursor.
+    /// `edges` is a mask of `wlr_edges`: the opposite edges stay where they are.
+    pub fn begin_resize(self: Pin<&mut Self>, view: Index, button: u32, edges: u32) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        if ctx.begin_grab(view, button, GrabMode::Resize(edges)) {
+            unsafe {
+                wlr_xdg_toplevel_set_resizing(ctx.views[view].xdg_surface, true);
+            }
+        }
+    }
+    pub fn end_grab(self: Pin<&mut Self>) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        ctx.end_grab_inner();
+    }
+    /// The view that is being moved or resized, if any.
+    pub fn grabbed_view(&self) -> Option<Index> {
+        self.grab.as_ref().map(|g| g.view)
+    }
+    fn begin_grab(&mut self, view: Index, button: u32, mode: GrabMode) -> bool {
+        self.end_grab_inner();
+        let ((x, y), r) = match self.views.get(view) {
+            Some(v) => (v.position(), v.get_rect()),
+            None => return false,
+        };
+        let (cursor_x, cursor_y) = self.get_cursor();
+        self.grab = Some(Grab {
+            view,
+            button,
+            mode,
+            cursor_x,
+            cursor_y,
+            rect: Rect { x, y, w: r.w, h: r.h },
+        });
+        true
+    }
+    fn end_grab_inner(&mut self) {
+        if let Some(grab) = self.grab.take() {
+            if let (GrabMode::Resize(_), Some(v)) = (grab.mode, self.views.get(grab.view)) {
+                unsafe {
+                    wlr_xdg_toplevel_set_resizing(v.xdg_surface, false);
+                }
+            }
+        }
+    }
+    fn update_grab(&mut self) {
+        let grab = match &self.grab {
+            Some(grab) => grab,
+            None => return,
+        };
+        let (cursor_x, cursor_y) = self.get_cursor();
+        let dx = (cursor_x - grab.cursor_x) as i32;
+        let dy = (cursor_y - grab.cursor_y) as i32;
+        let r = grab.rect;
+        let rect = match grab.mode {
+            GrabMode::Move => Rect {
+                x: r.x + dx,
+                y: r.y + dy,
+                ..r
+            },
+            GrabMode::Resize(edges) => {
+                let (mut left, mut right) = (r.x, r.x + r.w);
+                let (mut top, mut bottom) = (r.y, r.y + r.h);
+                if edges & wlr_edges::WLR_EDGE_TOP as u32 != 0 {
+                    top = (top + dy).min(bottom - 1);
+                } else if edges & wlr_edges::WLR_EDGE_BOTTOM as u32 != 0 {
+                    bottom = (bottom + dy).max(top + 1);
+                }
+                if edges & wlr_edges::WLR_EDGE_LEFT as u32 != 0 {
+                    left = (left + dx).min(right - 1);
+                } else if edges & wlr_edges::WLR_EDGE_RIGHT as u32 != 0 {
+                    right = (right + dx).max(left + 1);
+                }
+                self.views[grab.view]
+                    .as_mut()
+                    .set_size((right - left) as u32, (bottom - top) as u32);
+                // The client may not have committed the requested size yet, or
+                // may have clamped it: place the view from its committed size,
+                // so that the opposite edges don't drift
+                let committed = self.views[grab.view].get_rect();
+                Rect {
+                    x: if edges & wlr_edges::WLR_EDGE_LEFT as u32 != 0 {
+                        r.x + r.w - committed.w
+                    } else {
+                        r.x
+                    },
+                    y: if edges & wlr_edges::WLR_EDGE_TOP as u32 != 0 {
+                        r.y + r.h - committed.h
+                    } else {
+                        r.y
+                    },
+                    w: committed.w,
+                    h: committed.h,
+                }
+            }
+        };
+        self.views[grab.view].as_mut().set_position(rect.x, rect.y);
+        self.event_queue.push_back(Event::ViewGeometryChanged {
+            view: grab.view,
+            rect,
+        });
     }
     pub fn set_cursor_image(self: Pin<&mut Self>, name: &str) {
         let ctx = unsafe { self.get_unchecked_mut() };
+        let cname = std::ffi::CString::new(name).expect("null byte inside passed str");
         unsafe {
-            let cname = std::ffi::CString::new(name).expect("null byte inside passed str");
             wlr_xcursor_manager_set_cursor_image(ctx.cursor_mgr, cname.as_ptr(), ctx.cursor);
         }
+        ctx.cursor_image = Some(cname);
+        ctx.client_cursor.set(false);
+    }
+    /// Makes sure the xcursor theme is loaded at `scale`, and refreshes the
+    /// cursor image so that outputs with that scale get the right one.
+    fn load_cursor_theme(&mut self, scale: f32) {
+        unsafe {
+            wlr_xcursor_manager_load(self.cursor_mgr, scale);
+            if self.client_cursor.get() {
+                return;
+            }
+            if let Some(name) = &self.cursor_image {
+                wlr_xcursor_manager_set_cursor_image(self.cursor_mgr, name.as_ptr(), self.cursor);
+            }
+        }
+    }
+    fn output_index(&self, output: *mut wlr_output) -> Option<Index> {
+        self.outputs
+            .iter()
+            .find(|&(_, o)| o.output == output)
+            .map(|(idx, _)| idx)
+    }
+    /// Publishes the current state of the outputs to the output manager clients.
+    fn update_output_manager(&self) {
+        unsafe {
+            let config = wlr_output_configuration_v1_create();
+            for (_, o) in self.outputs.iter() {
+                let head = wlr_output_configuration_head_v1_create(config, o.output);
+                if let Some(r) = o.get_rect() {
+                    (*head).state.x = r.x;
+                    (*head).state.y = r.y;
+                }
+            }
+            wlr_output_manager_v1_set_configuration(self.output_manager, config);
+        }
+    }
+    /// The current state of an output, in the same form as a requested one.
+    fn output_head_config(&self, idx: Index) -> OutputHeadConfig {
+        let o = self.get_output(idx);
+        let rect = o.get_rect();
+        let mode = o.current_mode();
+        let custom_mode = unsafe {
+            if mode.is_none() && (*o.output).width > 0 {
+                Some(Mode {
+                    width: (*o.output).width,
+                    height: (*o.output).height,
+                    refresh: (*o.output).refresh,
+                    preferred: false,
+                })
+            } else {
+                None
+            }
+        };
+        OutputHeadConfig {
+            output: idx,
+            enabled: unsafe { (*o.output).enabled } && rect.is_some(),
+            mode,
+            custom_mode,
+            x: rect.map_or(0, |r| r.x),
+            y: rect.map_or(0, |r| r.y),
+            transform: o.transform(),
+            scale: o.scale(),
+        }
+    }
+    fn check_output_configuration(&self, heads: &[OutputHeadConfig]) -> bool {
+        heads.iter().enumerate().all(|(i, h)| {
+            let o = match self.outputs.get(h.output) {
+                Some(o) => o,
+                None => return false,
+            };
+            if heads[..i].iter().any(|prev| prev.output == h.output) {
+                return false;
+            }
+            if !h.enabled {
+                return true;
+            }
+            let mode_ok = match (h.mode, h.custom_mode) {
+                (Some(m), _) => o.modes().any(|o| {
+                    o.width == m.width && o.height == m.height && o.refresh == m.refresh
+                }),
+                (None, Some(m)) => m.width > 0 && m.height > 0,
+                (None, None) => true,
+            };
+            mode_ok && h.scale > 0.
+        })
+    }
+    fn apply_output_configuration(&mut self, heads: &[OutputHeadConfig], test_only: bool) -> bool {
+        if !self.check_output_configuration(heads) {
+            return false;
+        }
+        if test_only {
+            return true;
+        }
+        // Enabling outputs and setting modes are the only steps that can fail,
+        // so they are done for every head before anything else changes. If one
+        // fails the outputs get their previous mode back, while the layout,
+        // scales and transforms are left untouched.
+        let saved: Vec<_> = heads
+            .iter()
+            .map(|h| {
+                let mut s = self.output_head_config(h.output);
+                s.enabled = unsafe { (*self.outputs[h.output].output).enabled };
+                s
+            })
+            .collect();
+        for (i, h) in heads.iter().enumerate() {
+            if !self.apply_output_mode(h) {
+                wlr_log!(WLR_ERROR, "cannot apply output configuration, restoring the previous modes");
+                for s in saved[..=i].iter().rev() {
+                    if !self.apply_output_mode(s) {
+                        wlr_log!(WLR_ERROR, "cannot restore the previous mode of an output");
+                    }
+                }
+                return false;
+            }
+        }
+        for h in heads {
+            self.apply_output_placement(h);
+        }
+        true
+    }
+    /// Enables or disables the output of `head`, and sets its mode.
+    fn apply_output_mode(&mut self, head: &OutputHeadConfig) -> bool {
+        let output = self.outputs[head.output].output;
+        unsafe {
+            if !head.enabled {
+                return wlr_output_enable(output, false);
+            }
+            if !wlr_output_enable(output, true) {
+                return false;
+            }
+            match (head.mode, head.custom_mode) {
+                (Some(m), _) => self.outputs[head.output].set_mode(&m),
+                (None, Some(m)) => wlr_output_set_custom_mode(output, m.width, m.height, m.refresh),
+                (None, None) => true,
+            }
+        }
+    }
+    /// Sets the transform, scale and position of the output of `head`.
+    fn apply_output_placement(&mut self, head: &OutputHeadConfig) {
+        let output = self.outputs[head.output].output;
+        unsafe {
+            if !head.enabled {
+                wlr_output_layout_remove(self.output_layout, output);
+                return;
+            }
+            wlr_output_set_transform(output, head.transform);
+            wlr_output_set_scale(output, head.scale);
+            wlr_output_layout_add(self.output_layout, output, head.x, head.y);
+        }
+        self.load_cursor_theme(head.scale);
     }
     pub fn get_cursor(&self) -> (f64, f64) {
         unsafe {
@@ -195,24 +873,441 @@ impl Server {
     }
     pub fn pointer_clear_focus(&self) {
         unsafe {
+            if !(*self.seat).pointer_state.focused_surface.is_null() {
+                self.restore_cursor_image();
+            }
             wlr_seat_pointer_clear_focus(self.seat);
         }
     }
     pub fn pointer_notify_enter(&self, surface: &Surface, x: f64, y: f64) {
         unsafe {
+            let focused = (*self.seat).pointer_state.focused_surface;
+            if !focused.is_null() && focused != surface.surface {
+                self.restore_cursor_image();
+            }
             wlr_seat_pointer_notify_enter(self.seat, surface.surface, x, y);
         }
     }
+    /// Goes back to the last image set with `set_cursor_image` (`left_ptr` by default), replacing
+    /// the surface of a client that doesn't have pointer focus anymore.
+    fn restore_cursor_image(&self) {
+        self.client_cursor.set(false);
+        if let Some(name) = &self.cursor_image {
+            unsafe {
+                wlr_xcursor_manager_set_cursor_image(self.cursor_mgr, name.as_ptr(), self.cursor);
+            }
+        }
+    }
     pub fn pointer_notify_motion(&self, time_ms: u32, x: f64, y: f64) {
         unsafe {
             wlr_seat_pointer_notify_motion(self.seat, time_ms, x, y);
         }
     }
+    /// Returns the serial of the event, that clients send back in requests
+    /// that need a button press, like xdg_toplevel.move
+    pub fn pointer_notify_button(&self, time_ms: u32, button: u32, state: wlr_button_state) -> u32 {
+        unsafe { wlr_seat_pointer_notify_button(self.seat, time_ms, button, state) }
+    }
+    pub fn pointer_notify_axis(
+        &self,
+        time_ms: u32,
+        orientation: wlr_axis_orientation,
+        delta: f64,
+        delta_discrete: i32,
+        source: wlr_axis_source,
+    ) {
+        unsafe {
+            wlr_seat_pointer_notify_axis(self.seat, time_ms, orientation, delta, delta_discrete, source);
+        }
+    }
     pub fn pointer_notify_frame(&self) {
         unsafe {
             wlr_seat_pointer_notify_frame(self.seat);
         }
     }
+    /// Returns false if the keyboard was already removed, e.g. for the key
+    /// releases sent when it is unplugged.
+    pub fn keyboard_set_active(&self, keyboard: Index) -> bool {
+        match self.keyboards.get(keyboard) {
+            Some(k) => {
+                unsafe {
+                    wlr_seat_set_keyboard(self.seat, k.device);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn keyboard_clear_focus(&self) {
+        unsafe {
+            wlr_seat_keyboard_clear_focus(self.seat);
+        }
+    }
+    pub fn keyboard_notify_enter(&self, surface: &Surface) {
+        unsafe {
+            // The client gets the keys that are already pressed on the active keyboard
+            let keyboard = wlr_seat_get_keyboard(self.seat);
+            if keyboard.is_null() {
+                wlr_seat_keyboard_notify_enter(
+                    self.seat,
+                    surface.surface,
+                    std::ptr::null_mut(),
+                    0,
+                    std::ptr::null_mut(),
+                );
+            } else {
+                wlr_seat_keyboard_notify_enter(
+                    self.seat,
+                    surface.surface,
+                    (*keyboard).keycodes.as_mut_ptr(),
+                    (*keyboard).num_keycodes,
+                    &mut (*keyboard).modifiers as *mut _,
+                );
+            }
+        }
+    }
+    pub fn keyboard_notify_enter_view(&self, view: Index) {
+        self.keyboard_notify_enter(&self.get_view(view).surface());
+    }
+    pub fn keyboard_notify_key(&self, time_ms: u32, keycode: u32, state: wlr_key_state) {
+        unsafe {
+            wlr_seat_keyboard_notify_key(self.seat, time_ms, keycode, state as u32);
+        }
+    }
+    pub fn keyboard_notify_modifiers(&self, modifiers: &wlr_keyboard_modifiers) {
+        unsafe {
+            wlr_seat_keyboard_notify_modifiers(self.seat, modifiers as *const _ as *mut _);
+        }
+    }
+    /// Sets the keymap of every keyboard, including the ones plugged in later.
+    pub fn set_keymap(self: Pin<&mut Self>, config: KeymapConfig) -> Result<(), ServerError> {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        unsafe {
+            let context = xkb_context_new(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                return Err(ServerError::Keymap);
+            }
+            let keymap = config.compile(context);
+            xkb_context_unref(context);
+            let keymap = keymap?;
+            for (_, k) in ctx.keyboards.iter() {
+                wlr_keyboard_set_keymap(k.keyboard, keymap);
+            }
+            xkb_keymap_unref(keymap);
+        }
+        ctx.keymap_config = config;
+        Ok(())
+    }
+    /// Sets the key repeat rate (in keys per second) and delay (in milliseconds)
+    /// of every keyboard, including the ones plugged in later.
+    pub fn set_repeat_info(self: Pin<&mut Self>, rate: i32, delay: i32) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        for (_, k) in ctx.keyboards.iter() {
+            k.set_repeat_info(rate, delay);
+        }
+        ctx.repeat_rate = rate;
+        ctx.repeat_delay = delay;
+    }
+    /// Places an output at the given position of the layout, in layout coordinates.
+    /// Outputs that are not placed explicitly are arranged left to right.
+    pub fn set_output_position(&self, output: Index, x: i32, y: i32) {
+        unsafe {
+            wlr_output_layout_add(self.output_layout, self.outputs[output].output, x, y);
+        }
+    }
+    /// The box of every output that is part of the layout, in layout coordinates.
+    pub fn output_layout_boxes<'a>(&'a self) -> impl Iterator<Item = (Index, Rect)> + 'a {
+        self.outputs
+            .iter()
+            .filter_map(|(idx, o)| o.get_rect().map(|r| (idx, r)))
+    }
+    /// The heads of an output configuration requested by a client.
+    /// Heads of outputs that don't exist anymore are skipped, and there are
+    /// none if the configuration was already answered.
+    pub fn output_configuration(&self, config: Index) -> Vec<OutputHeadConfig> {
+        let mut heads = Vec::new();
+        let c = match self.output_configs.get(config) {
+            Some(pending) => pending.config,
+            None => return heads,
+        };
+        unsafe {
+            wl_list_for_each!((*c).heads, link, (head: wlr_output_configuration_head_v1) => {
+                let state = &(*head).state;
+                if let Some(output) = self.output_index(state.output) {
+                    let custom_mode = if state.mode.is_null() && state.custom_mode.width > 0 {
+                        Some(Mode {
+                            width: state.custom_mode.width,
+                            height: state.custom_mode.height,
+                            refresh: state.custom_mode.refresh,
+                            preferred: false,
+                        })
+                    } else {
+                        None
+                    };
+                    heads.push(OutputHeadConfig {
+                        output,
+                        enabled: state.enabled,
+                        mode: if state.mode.is_null() {
+                            None
+                        } else {
+                            Some(Mode::from_wlr(&*state.mode))
+                        },
+                        custom_mode,
+                        x: state.x,
+                        y: state.y,
+                        transform: state.transform,
+                        scale: state.scale,
+                    });
+                }
+            });
+        }
+        heads
+    }
+    /// Applies (or only tests, if the client asked for a test) an output
+    /// configuration, and tells the client whether it succeeded.
+    /// Either every head is applied or the outputs are left as they were.
+    /// Returns false if the configuration failed or was already answered.
+    pub fn accept_output_configuration(self: Pin<&mut Self>, config: Index) -> bool {
+        let heads = self.output_configuration(config);
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let pending = match ctx.output_configs.remove(config) {
+            Some(pending) => pending,
+            None => return false,
+        };
+        let ok = ctx.apply_output_configuration(&heads, pending.test);
+        unsafe {
+            if ok {
+                wlr_output_configuration_v1_send_succeeded(pending.config);
+            } else {
+                wlr_output_configuration_v1_send_failed(pending.config);
+            }
+            wlr_output_configuration_v1_destroy(pending.config);
+        }
+        if ok && !pending.test {
+            ctx.update_output_manager();
+        }
+        ok
+    }
+    pub fn reject_output_configuration(self: Pin<&mut Self>, config: Index) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let pending = match ctx.output_configs.remove(config) {
+            Some(pending) => pending,
+            None => return,
+        };
+        unsafe {
+            wlr_output_configuration_v1_send_failed(pending.config);
+            wlr_output_configuration_v1_destroy(pending.config);
+        }
+    }
+    /// Sets the function that picks the initial mode of new outputs.
+    /// It is also applied right away to the outputs that already exist, since
+    /// those are usually created when the backend starts.
+    /// The default policy is `preferred_mode`.
+    pub fn set_output_mode_policy(
+        self: Pin<&mut Self>,
+        policy: impl FnMut(&Output) -> Option<Mode> + 'static,
+    ) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        ctx.mode_policy = Box::new(policy);
+        for (_, o) in ctx.outputs.iter() {
+            if let Some(mode) = (ctx.mode_policy)(o.as_ref().get_ref()) {
+                o.set_mode(&mode);
+            }
+        }
+    }
+    /// Switches the active layout group of every keyboard.
+    pub fn set_layout_group(&self, group: u32) {
+        for (_, k) in self.keyboards.iter() {
+            k.set_layout_group(group);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ServerError {
+    DisplayCreation,
+    Backend,
+    Renderer,
+    /// A protocol global, named by its interface, could not be created
+    Global(&'static str),
+    OutputLayout,
+    Cursor,
+    Seat,
+    Socket,
+    BackendStart,
+    Keymap,
+}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ServerError::DisplayCreation => write!(f, "cannot create wayland display"),
+            ServerError::Backend => write!(f, "cannot create backend"),
+            ServerError::Renderer => write!(f, "cannot create renderer"),
+            ServerError::Global(name) => write!(f, "cannot create {} global", name),
+            ServerError::OutputLayout => write!(f, "cannot create output layout"),
+            ServerError::Cursor => write!(f, "cannot create cursor"),
+            ServerError::Seat => write!(f, "cannot create seat"),
+            ServerError::Socket => write!(f, "cannot create socket"),
+            ServerError::BackendStart => write!(f, "cannot start backend"),
+            ServerError::Keymap => write!(f, "cannot compile keymap"),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+#[derive(Debug, Clone)]
+pub enum BackendKind {
+    /// Let wlroots pick the backend from the environment
+    Auto,
+    /// No real outputs nor input devices, see `ServerBuilder::headless_outputs`
+    Headless,
+    /// One output in a window of a running Wayland compositor
+    Wayland,
+    /// One output in a window of a running X11 server
+    X11,
+    /// DRM/KMS outputs of every GPU and libinput devices of a new session
+    Drm,
+    /// Several backends at the same time
+    Multi(Vec<BackendKind>),
+}
+
+enum SocketKind {
+    Auto,
+    Name(String),
+    Fd(std::os::unix::io::RawFd),
+}
+
+pub struct ServerBuilder {
+    backend: BackendKind,
+    headless_outputs: Vec<(u32, u32)>,
+    socket: SocketKind,
+    set_wayland_display: bool,
+}
+
+impl ServerBuilder {
+    pub fn new() -> ServerBuilder {
+        ServerBuilder {
+            backend: BackendKind::Auto,
+            headless_outputs: Vec::new(),
+            socket: SocketKind::Auto,
+            set_wayland_display: false,
+        }
+    }
+    pub fn backend(mut self, kind: BackendKind) -> Self {
+        self.backend = kind;
+        self
+    }
+    /// Adds `count` virtual outputs of the given resolution to the headless backend.
+    pub fn headless_outputs(mut self, count: usize, width: u32, height: u32) -> Self {
+        self.headless_outputs
+            .extend(std::iter::repeat((width, height)).take(count));
+        self
+    }
+    /// Binds to `$XDG_RUNTIME_DIR/name` instead of the first free `wayland-N`.
+    pub fn socket_name(mut self, name: &str) -> Self {
+        self.socket = SocketKind::Name(name.to_owned());
+        self
+    }
+    /// Listens on a socket that is already bound and listening, e.g. one
+    /// passed by a supervisor. The server takes ownership of `fd`.
+    pub fn socket_fd(mut self, fd: std::os::unix::io::RawFd) -> Self {
+        self.socket = SocketKind::Fd(fd);
+        self
+    }
+    /// Sets WAYLAND_DISPLAY in the environment of the process to the socket
+    /// name, so that child processes connect to this server.
+    pub fn set_wayland_display(mut self, set: bool) -> Self {
+        self.set_wayland_display = set;
+        self
+    }
+    pub fn build(self) -> Result<Pin<Box<Server>>, ServerError> {
+        Server::from_builder(&self)
+    }
+    unsafe fn create_backend(&self, display: *mut wl_display, kind: &BackendKind) -> *mut wlr_backend {
+        match kind {
+            BackendKind::Auto => wlr_backend_autocreate(display, None),
+            BackendKind::Headless => {
+                let backend = wlr_headless_backend_create(display, None);
+                if !backend.is_null() {
+                    for &(width, height) in &self.headless_outputs {
+                        wlr_headless_add_output(backend, width, height);
+                    }
+                }
+                backend
+            }
+            BackendKind::Wayland => {
+                // a null remote means $WAYLAND_DISPLAY
+                let backend = wlr_wl_backend_create(display, std::ptr::null(), None);
+                if !backend.is_null() {
+                    wlr_wl_output_create(backend);
+                }
+                backend
+            }
+            BackendKind::X11 => {
+                // a null display means $DISPLAY
+                let backend = wlr_x11_backend_create(display, std::ptr::null(), None);
+                if !backend.is_null() {
+                    wlr_x11_output_create(backend);
+                }
+                backend
+            }
+            BackendKind::Drm => {
+                let session = wlr_session_create(display);
+                if session.is_null() {
+                    return std::ptr::null_mut();
+                }
+                let multi = wlr_multi_backend_create(display);
+                let libinput = wlr_libinput_backend_create(display, session);
+                if libinput.is_null() {
+                    wlr_backend_destroy(multi);
+                    wlr_session_destroy(session);
+                    return std::ptr::null_mut();
+                }
+                wlr_multi_backend_add(multi, libinput);
+
+                let mut gpus = [0; 8];
+                let num_gpus = wlr_session_find_gpus(session, gpus.len(), gpus.as_mut_ptr());
+                // the first GPU that works renders for the others
+                let mut primary_drm = std::ptr::null_mut();
+                for &gpu in &gpus[..num_gpus] {
+                    let drm = wlr_drm_backend_create(display, session, gpu, primary_drm, None);
+                    if drm.is_null() {
+                        continue;
+                    }
+                    if primary_drm.is_null() {
+                        primary_drm = drm;
+                    }
+                    wlr_multi_backend_add(multi, drm);
+                }
+                if primary_drm.is_null() {
+                    wlr_backend_destroy(multi);
+                    wlr_session_destroy(session);
+                    return std::ptr::null_mut();
+                }
+                multi
+            }
+            BackendKind::Multi(kinds) => {
+                let multi = wlr_multi_backend_create(display);
+                for kind in kinds {
+                    let backend = self.create_backend(display, kind);
+                    if backend.is_null() {
+                        wlr_backend_destroy(multi);
+                        return std::ptr::null_mut();
+                    }
+                    wlr_multi_backend_add(multi, backend);
+                }
+                multi
+            }
+        }
+    }
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        ServerBuilder::new()
+    }
 }
 
 impl std::ops::Drop for Server {
@@ -224,12 +1319,29 @@ impl std::ops::Drop for Server {
             // Actual drop code goes here.
             unsafe {
                 let ctx = this.as_mut().get_unchecked_mut();
-                wlr_backend_destroy(ctx.backend);
-                ffi_dispatch!(
-                    WAYLAND_SERVER_HANDLE,
-                    wl_display_destroy,
-                    ctx.display as *mut _
-                );
+                // Any of these can be null if `Server::new` failed halfway.
+                // The globals and the seat are destroyed with the display.
+                // The backend goes first: destroying it destroys the input
+                // devices, which get detached from the cursor.
+                if !ctx.backend.is_null() {
+                    wlr_backend_destroy(ctx.backend);
+                }
+                if !ctx.cursor.is_null() {
+                    wlr_cursor_destroy(ctx.cursor);
+                }
+                if !ctx.cursor_mgr.is_null() {
+                    wlr_xcursor_manager_destroy(ctx.cursor_mgr);
+                }
+                if !ctx.output_layout.is_null() {
+                    wlr_output_layout_destroy(ctx.output_layout);
+                }
+                if !ctx.display.is_null() {
+                    ffi_dispatch!(
+                        WAYLAND_SERVER_HANDLE,
+                        wl_display_destroy,
+                        ctx.display as *mut _
+                    );
+                }
             }
         }
     }
@@ -254,22 +1366,37 @@ implement_listener!(
     request_set_cursor,
     wlr_seat_pointer_request_set_cursor_event
 );
+implement_listener!(Server, output_layout, change, libc::c_void);
+implement_listener!(
+    Server,
+    output_manager,
+    apply,
+    wlr_output_configuration_v1
+);
+implement_listener!(
+    Server,
+    output_manager,
+    test,
+    wlr_output_configuration_v1
+);
 impl Server {
     fn backend_new_output(self: Pin<&mut Self>, output_ptr: *mut wlr_output) {
         wlr_log!(WLR_INFO, "new output!");
 
-        let output = Output::new(&self.as_ref(), output_ptr);
-        unsafe {
-            // check that list is not empty
-            if (*output_ptr).modes.next != &(*output_ptr).modes as *const _ as *mut _ {
-                let mode = container_of!((*output_ptr).modes.prev, wlr_output_mode, link);
-                wlr_output_set_mode(output_ptr, mode);
-            }
-            wlr_output_layout_add_auto(self.as_ref().output_layout, output_ptr);
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let output = Output::new(ctx, output_ptr);
+        if let Some(mode) = (ctx.mode_policy)(output.as_ref().get_ref()) {
+            output.set_mode(&mode);
         }
+        ctx.load_cursor_theme(unsafe { (*output_ptr).scale });
 
-        let ctx = unsafe { self.get_unchecked_mut() };
-        ctx.outputs.insert(output);
+        // the layout change publishes the heads, so the output must be in the arena
+        let idx = ctx.outputs.insert(output);
+        unsafe {
+            wlr_output_layout_add_auto(ctx.output_layout, output_ptr);
+        }
+        ctx.event_queue
+            .push_back(Event::OutputNew { output: idx });
     }
     fn backend_new_input(self: Pin<&mut Self>, input_ptr: *mut wlr_input_device) {
         // UNSAFE: promise that we will not move the value out of ctx
@@ -279,13 +1406,32 @@ impl Server {
             wlr_input_device_type::WLR_INPUT_DEVICE_POINTER => unsafe {
                 wlr_cursor_attach_input_device(ctx.cursor, input_ptr);
             },
-            wlr_input_device_type::WLR_INPUT_DEVICE_KEYBOARD => {}
+            wlr_input_device_type::WLR_INPUT_DEVICE_KEYBOARD => {
+                let keyboard = Keyboard::new(ctx, input_ptr);
+                ctx.keyboards.insert(keyboard);
+            }
             _ => {}
         }
 
-        let caps = Capability::Pointer;
+        let device = InputDevice::new(ctx, input_ptr);
+        let info = device.info();
+        let idx = ctx.input_devices.insert(device);
+        ctx.update_capabilities();
+        ctx.event_queue
+            .push_back(Event::InputDeviceAdded { device: idx, info });
+    }
+    fn update_capabilities(&self) {
+        let mut caps = Capability::empty();
+        for (_, d) in self.input_devices.iter() {
+            match unsafe { (*d.device).type_ } {
+                wlr_input_device_type::WLR_INPUT_DEVICE_POINTER => caps |= Capability::Pointer,
+                wlr_input_device_type::WLR_INPUT_DEVICE_KEYBOARD => caps |= Capability::Keyboard,
+                wlr_input_device_type::WLR_INPUT_DEVICE_TOUCH => caps |= Capability::Touch,
+                _ => {}
+            }
+        }
         unsafe {
-            wlr_seat_set_capabilities(ctx.seat, caps.to_raw());
+            wlr_seat_set_capabilities(self.seat, caps.to_raw());
         }
     }
     fn xdg_shell_new_surface(self: Pin<&mut Self>, surface_ptr: *mut wlr_xdg_surface) {
@@ -324,6 +1470,11 @@ impl Server {
     fn cursor_button(self: Pin<&mut Self>, event: *mut wlr_event_pointer_button) {
         let e = unsafe { &*(event) };
         let ctx = unsafe { self.get_unchecked_mut() };
+        if e.state == wlr_button_state::WLR_BUTTON_RELEASED
+            && ctx.grab.as_ref().map(|g| g.button) == Some(e.button)
+        {
+            ctx.end_grab_inner();
+        }
         ctx.event_queue.push_back(Event::CursorButton {
             time_ms: e.time_msec,
             state: e.state,
@@ -349,7 +1500,43 @@ impl Server {
         self: Pin<&mut Self>,
         event: *mut wlr_seat_pointer_request_set_cursor_event,
     ) {
-        println!("request set cursor!");
+        let e = unsafe { &*(event) };
+        let ctx = unsafe { self.get_unchecked_mut() };
+        // Any client can send the request, only the one with pointer focus
+        // gets to change the cursor
+        if unsafe { (*ctx.seat).pointer_state.focused_client } != e.seat_client {
+            return;
+        }
+        unsafe {
+            wlr_cursor_set_surface(ctx.cursor, e.surface, e.hotspot_x, e.hotspot_y);
+        }
+        ctx.client_cursor.set(true);
+        ctx.event_queue.push_back(Event::CursorSurfaceSet {
+            hidden: e.surface.is_null(),
+            hotspot_x: e.hotspot_x,
+            hotspot_y: e.hotspot_y,
+        });
+    }
+    fn output_layout_change(self: Pin<&mut Self>, _: *mut libc::c_void) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        ctx.update_output_manager();
+        ctx.event_queue.push_back(Event::OutputLayoutChange);
+    }
+    fn output_manager_apply(self: Pin<&mut Self>, config: *mut wlr_output_configuration_v1) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let idx = ctx
+            .output_configs
+            .insert(PendingOutputConfig { config, test: false });
+        ctx.event_queue
+            .push_back(Event::OutputConfigurationApply { config: idx });
+    }
+    fn output_manager_test(self: Pin<&mut Self>, config: *mut wlr_output_configuration_v1) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let idx = ctx
+            .output_configs
+            .insert(PendingOutputConfig { config, test: true });
+        ctx.event_queue
+            .push_back(Event::OutputConfigurationTest { config: idx });
     }
 }
 
@@ -359,6 +1546,7 @@ pub struct Output {
     output: *mut wlr_output,
 
     output_frame_listener: wl_listener,
+    output_destroy_listener: wl_listener,
 }
 
 impl Output {
@@ -367,6 +1555,7 @@ impl Output {
             server: server as *const _ as *mut _,
             output,
             output_frame_listener: unsafe { std::mem::zeroed() },
+            output_destroy_listener: unsafe { std::mem::zeroed() },
         };
         let mut o = Box::pin(o);
 
@@ -374,12 +1563,111 @@ impl Output {
             let ctx = o.as_mut().get_unchecked_mut();
 
             connect_listener!(ctx, output, frame);
+            connect_listener!(ctx, output, destroy);
 
             wlr_output_create_global(output);
         }
 
         o
     }
+    pub fn name(&self) -> String {
+        unsafe { c_chars_to_string(&(*self.output).name) }
+    }
+    pub fn make(&self) -> String {
+        unsafe { c_chars_to_string(&(*self.output).make) }
+    }
+    pub fn model(&self) -> String {
+        unsafe { c_chars_to_string(&(*self.output).model) }
+    }
+    pub fn serial(&self) -> String {
+        unsafe { c_chars_to_string(&(*self.output).serial) }
+    }
+    /// Physical width and height of the output in millimeters (0 if unknown).
+    pub fn physical_size(&self) -> (i32, i32) {
+        unsafe { ((*self.output).phys_width, (*self.output).phys_height) }
+    }
+    pub fn modes(&self) -> impl Iterator<Item = Mode> {
+        let mut modes = Vec::new();
+        unsafe {
+            wl_list_for_each!((*self.output).modes, link, (mode: wlr_output_mode) => {
+                modes.push(Mode::from_wlr(&*mode));
+            });
+        }
+        modes.into_iter()
+    }
+    /// Sets one of the modes advertised by the output.
+    /// Returns false if the mode is not in `modes()` or the backend rejects it.
+    pub fn set_mode(&self, mode: &Mode) -> bool {
+        unsafe {
+            let mut found = std::ptr::null_mut();
+            wl_list_for_each!((*self.output).modes, link, (m: wlr_output_mode) => {
+                if found.is_null()
+                    && (*m).width == mode.width
+                    && (*m).height == mode.height
+                    && (*m).refresh == mode.refresh
+                {
+                    found = m;
+                }
+            });
+            if found.is_null() {
+                return false;
+            }
+            wlr_output_set_mode(self.output, found)
+        }
+    }
+    /// Sets a mode that is not advertised by the output. `refresh` is in mHz,
+    /// 0 lets the backend pick one.
+    pub fn set_custom_mode(&self, width: i32, height: i32, refresh: i32) -> bool {
+        unsafe { wlr_output_set_custom_mode(self.output, width, height, refresh) }
+    }
+    pub fn current_mode(&self) -> Option<Mode> {
+        unsafe {
+            let mode = (*self.output).current_mode;
+            if mode.is_null() {
+                None
+            } else {
+                Some(Mode::from_wlr(&*mode))
+            }
+        }
+    }
+    /// The box of the output in layout coordinates, or `None` if the output
+    /// is not part of the layout.
+    pub fn get_rect(&self) -> Option<Rect> {
+        unsafe {
+            let server = &*self.server;
+            let b = wlr_output_layout_get_box(server.output_layout, self.output);
+            if b.is_null() {
+                None
+            } else {
+                Some(Rect {
+                    x: (*b).x,
+                    y: (*b).y,
+                    w: (*b).width,
+                    h: (*b).height,
+                })
+            }
+        }
+    }
+    pub fn scale(&self) -> f32 {
+        unsafe { (*self.output).scale }
+    }
+    pub fn set_scale(self: Pin<&mut Self>, scale: f32) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let server = unsafe { &mut (*ctx.server) };
+        unsafe {
+            wlr_output_set_scale(ctx.output, scale);
+        }
+        server.load_cursor_theme(scale);
+    }
+    pub fn transform(&self) -> wl_output_transform {
+        unsafe { (*self.output).transform }
+    }
+    pub fn set_transform(self: Pin<&mut Self>, transform: wl_output_transform) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        unsafe {
+            wlr_output_set_transform(ctx.output, transform);
+        }
+    }
     pub fn render_views(self: Pin<&mut Self>, views: impl Iterator<Item = (Index, Rect)>) {
         let ctx = unsafe { self.get_unchecked_mut() };
         let server = unsafe { &mut (*ctx.server) };
@@ -389,10 +1677,9 @@ impl Output {
             if !wlr_output_attach_render(ctx.output, std::ptr::null_mut()) {
                 return;
             }
-            let mut w: i32 = 0;
-            let mut h: i32 = 0;
-            wlr_output_effective_resolution(ctx.output, &mut w as *mut _, &mut h as *mut _);
-            wlr_renderer_begin(renderer, w, h);
+            // The renderer works in buffer pixels, scale and transform are
+            // applied by the projection below
+            wlr_renderer_begin(renderer, (*ctx.output).width, (*ctx.output).height);
             let color = [0.3, 0.3, 0.3, 1.0];
             wlr_renderer_clear(renderer, color.as_ptr());
 
@@ -475,6 +1762,7 @@ impl Output {
 }
 
 implement_listener!(Output, output, frame, libc::c_void);
+implement_listener!(Output, output, destroy, libc::c_void);
 impl Output {
     fn output_frame(self: Pin<&mut Self>, _: *mut libc::c_void) {
         let ctx = unsafe { self.get_unchecked_mut() };
@@ -489,6 +1777,27 @@ impl Output {
             when: std::time::Instant::now(),
         });
     }
+    fn output_destroy(self: Pin<&mut Self>, _: *mut libc::c_void) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let server = unsafe { &mut (*ctx.server) };
+        let (index, _) = server
+            .outputs
+            .iter()
+            .find(|&(_, o)| o.as_ref().get_ref() as *const _ == ctx as *const _)
+            .expect("cant find output in arena");
+        let o = server
+            .outputs
+            .remove(index)
+            .expect("cant find output to remove");
+        unsafe {
+            wlr_output_layout_remove(server.output_layout, ctx.output);
+        }
+        server.dead_outputs.push(o);
+
+        server
+            .event_queue
+            .push_back(Event::OutputDestroy { output: index });
+    }
 }
 
 #[repr(C)]
@@ -501,6 +1810,40 @@ pub struct View {
     xdg_surface_destroy_listener: wl_listener,
     xdg_surface_request_move_listener: wl_listener,
     xdg_surface_request_resize_listener: wl_listener,
+    surface_commit_listener: wl_listener,
+
+    x: i32,
+    y: i32,
+    pending_sizes: VecDeque<PendingSize>,
+    committed_size: Option<(u32, u32)>,
+    geometry: Option<ViewGeometry>,
+}
+
+/// A size sent to the client that it has not acknowledged yet
+#[derive(Debug, PartialEq)]
+struct PendingSize {
+    serial: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Removes the sizes of a configure that was cancelled before being sent.
+fn discard_unsent_sizes(pending: &mut VecDeque<PendingSize>, unsent: u32) {
+    pending.retain(|p| p.serial != unsent);
+}
+
+/// Removes the sizes sent up to the `acked` configure and returns the newest.
+/// Acking a configure also acks the older ones, which it supersedes.
+fn take_acked_size(pending: &mut VecDeque<PendingSize>, acked: u32) -> Option<PendingSize> {
+    let mut size = None;
+    while let Some(p) = pending.front() {
+        // serials wrap around: compare their distance instead of their value
+        if (acked.wrapping_sub(p.serial) as i32) < 0 {
+            break;
+        }
+        size = pending.pop_front();
+    }
+    size
 }
 
 impl View {
@@ -514,6 +1857,13 @@ impl View {
             xdg_surface_destroy_listener: unsafe { std::mem::zeroed() },
             xdg_surface_request_move_listener: unsafe { std::mem::zeroed() },
             xdg_surface_request_resize_listener: unsafe { std::mem::zeroed() },
+            surface_commit_listener: unsafe { std::mem::zeroed() },
+
+            x: 0,
+            y: 0,
+            pending_sizes: VecDeque::new(),
+            committed_size: None,
+            geometry: None,
         };
         let mut v = Box::pin(v);
 
@@ -524,6 +1874,9 @@ impl View {
             connect_listener!(ctx, xdg_surface, unmap);
             connect_listener!(ctx, xdg_surface, destroy);
 
+            let surface = (*ctx.xdg_surface).surface;
+            connect_listener!(ctx, surface, surface, commit);
+
             let toplevel =
                 (*ctx.xdg_surface).__bindgen_anon_1.toplevel as *const _ as *mut wlr_xdg_toplevel;
             let toplevel = &mut (*toplevel);
@@ -545,9 +1898,114 @@ impl View {
             Rect {
                 x: geo_box.x,
                 y: geo_box.y,
-                w: geo_box.height,
-                h: geo_box.width,
+                w: geo_box.width,
+                h: geo_box.height,
+            }
+        }
+    }
+    pub fn geometry(&self) -> ViewGeometry {
+        let extents = unsafe {
+            let mut ext_box = wlr_box {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            };
+            wlr_surface_get_extends((*self.xdg_surface).surface, &mut ext_box as *mut _);
+            Rect {
+                x: ext_box.x,
+                y: ext_box.y,
+                w: ext_box.width,
+                h: ext_box.height,
+            }
+        };
+        ViewGeometry {
+            window: self.get_rect(),
+            extents,
+            scale: unsafe { (*(*self.xdg_surface).surface).current.scale },
+        }
+    }
+    /// Asks the client to resize the view and returns the serial of the
+    /// configure, or 0 if the client already has that size. The new size
+    /// is reported by `ViewCommit` once the client acknowledges it.
+    pub fn set_size(self: Pin<&mut Self>, width: u32, height: u32) -> u32 {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        // the configure that is scheduled but not sent yet, if any
+        let scheduled = unsafe {
+            if (*ctx.xdg_surface).configure_idle.is_null() {
+                None
+            } else {
+                Some((*ctx.xdg_surface).configure_next_serial)
+            }
+        };
+        let serial = unsafe { wlr_xdg_toplevel_set_size(ctx.xdg_surface, width, height) };
+        if serial != 0 {
+            ctx.pending_sizes.push_back(PendingSize {
+                serial,
+                width,
+                height,
+            });
+        } else if let Some(scheduled) = scheduled {
+            // The size is the one the client already has or was last sent, so
+            // wlroots cancelled the scheduled configure: it will never be acked
+            discard_unsent_sizes(&mut ctx.pending_sizes, scheduled);
+        }
+        serial
+    }
+    /// The last size requested with `set_size` that the client has not
+    /// acknowledged yet.
+    pub fn pending_size(&self) -> Option<(u32, u32)> {
+        self.pending_sizes.back().map(|p| (p.width, p.height))
+    }
+    /// The last size requested with `set_size` that the client acknowledged.
+    pub fn committed_size(&self) -> Option<(u32, u32)> {
+        self.committed_size
+    }
+    /// Sets the position of the window geometry of the view in layout
+    /// coordinates. Move and resize grabs update it.
+    pub fn set_position(self: Pin<&mut Self>, x: i32, y: i32) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        ctx.x = x;
+        ctx.y = y;
+    }
+    pub fn position(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+    pub fn surface<'a>(&'a self) -> Surface<'a> {
+        Surface {
+            surface: unsafe { (*self.xdg_surface).surface },
+            _lifetime: std::marker::PhantomData,
+        }
+    }
+    /// The pid of the client that created the view.
+    pub fn pid(&self) -> Option<pid_t> {
+        unsafe {
+            let resource = (*(*self.xdg_surface).surface).resource;
+            if resource.is_null() {
+                return None;
             }
+            let client = ffi_dispatch!(
+                WAYLAND_SERVER_HANDLE,
+                wl_resource_get_client,
+                resource as *mut _
+            );
+            let mut pid: pid_t = 0;
+            let mut uid: uid_t = 0;
+            let mut gid: gid_t = 0;
+            ffi_dispatch!(
+                WAYLAND_SERVER_HANDLE,
+                wl_client_get_credentials,
+                client,
+                &mut pid as *mut _,
+                &mut uid as *mut _,
+                &mut gid as *mut _
+            );
+            Some(pid)
+        }
+    }
+    pub fn set_activated(&self, activated: bool) {
+        unsafe {
+            wlr_xdg_toplevel_set_activated(self.xdg_surface, activated);
         }
     }
     pub fn surface_at<'a>(&'a self, rel_x: f64, rel_y: f64) -> Option<SurfaceHit<'a>> {
@@ -575,14 +2033,58 @@ impl View {
 implement_listener!(View, xdg_surface, map, libc::c_void);
 implement_listener!(View, xdg_surface, unmap, libc::c_void);
 implement_listener!(View, xdg_surface, destroy, libc::c_void);
-implement_listener!(View, xdg_surface, request_move, libc::c_void);
+implement_listener!(
+    View,
+    xdg_surface,
+    request_move,
+    wlr_xdg_toplevel_move_event
+);
 implement_listener!(
     View,
     xdg_surface,
     request_resize,
     wlr_xdg_toplevel_resize_event
 );
+implement_listener!(View, surface, commit, libc::c_void);
 impl View {
+    fn surface_commit(self: Pin<&mut Self>, _: *mut libc::c_void) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let acked = unsafe { (*ctx.xdg_surface).configure_serial };
+        let serial = match take_acked_size(&mut ctx.pending_sizes, acked) {
+            Some(size) => {
+                ctx.committed_size = Some((size.width, size.height));
+                Some(size.serial)
+            }
+            None => None,
+        };
+        let geometry = ctx.geometry();
+        if serial.is_none() && ctx.geometry == Some(geometry) {
+            return;
+        }
+        ctx.geometry = Some(geometry);
+        let server = unsafe { &mut (*ctx.server) };
+        let (index, _) = server
+            .views
+            .iter()
+            .find(|&(_, o)| o.as_ref().get_ref() as *const _ == ctx as *const _)
+            .expect("cant find view in arena");
+        server.event_queue.push_back(Event::ViewCommit {
+            view: index,
+            serial,
+            geometry,
+        });
+        // a resize in progress is anchored to the committed size
+        if let Some(Grab {
+            view,
+            mode: GrabMode::Resize(_),
+            ..
+        }) = server.grab
+        {
+            if view == index {
+                server.update_grab();
+            }
+        }
+    }
     fn xdg_surface_map(self: Pin<&mut Self>, _: *mut libc::c_void) {
         let ctx = unsafe { self.get_unchecked_mut() };
         let server = unsafe { &mut (*ctx.server) };
@@ -603,12 +2105,23 @@ impl View {
             .iter()
             .find(|&(_, o)| o.as_ref().get_ref() as *const _ == ctx as *const _)
             .expect("cant find view in arena");
+        if server.grabbed_view() == Some(index) {
+            server.end_grab_inner();
+        }
         server
             .event_queue
             .push_back(Event::XdgSurfaceUnmap { view: index });
     }
     fn xdg_surface_destroy(self: Pin<&mut Self>, _: *mut libc::c_void) {
         let ctx = unsafe { self.get_unchecked_mut() };
+        // the wl_surface can outlive its xdg role
+        unsafe {
+            ffi_dispatch!(
+                WAYLAND_SERVER_HANDLE,
+                wl_list_remove,
+                &mut ctx.surface_commit_listener.link as *mut _ as _
+            );
+        }
         let server = unsafe { &mut (*ctx.server) };
         let (index, _) = server
             .views
@@ -620,13 +2133,31 @@ impl View {
             .remove(index)
             .expect("cant find view to remove");
         server.dead_views.push(v);
+        if server.grabbed_view() == Some(index) {
+            server.grab = None;
+        }
 
         server
             .event_queue
             .push_back(Event::XdgSurfaceDestroy { view: index });
     }
-    fn xdg_surface_request_move(self: Pin<&mut Self>, _: *mut libc::c_void) {
+    /// Move and resize requests are only valid in response to a click on
+    /// the view, or one of its subsurfaces such as a CSD titlebar.
+    fn validate_grab_serial(&self, serial: u32) -> bool {
+        let server = unsafe { &*self.server };
+        unsafe {
+            let focused = (*server.seat).pointer_state.focused_surface;
+            !focused.is_null()
+                && wlr_surface_get_root_surface(focused) == (*self.xdg_surface).surface
+                && wlr_seat_validate_pointer_grab_serial(server.seat, focused, serial)
+        }
+    }
+    fn xdg_surface_request_move(self: Pin<&mut Self>, event: *mut wlr_xdg_toplevel_move_event) {
+        let e = unsafe { &*(event) };
         let ctx = unsafe { self.get_unchecked_mut() };
+        if !ctx.validate_grab_serial(e.serial) {
+            return;
+        }
         let server = unsafe { &mut (*ctx.server) };
         let (index, _) = server
             .views
@@ -635,11 +2166,18 @@ impl View {
             .expect("cant find view in arena");
         server
             .event_queue
-            .push_back(Event::XdgToplevelRequestMove { view: index });
+            .push_back(Event::XdgToplevelRequestMove {
+                view: index,
+                serial: e.serial,
+                button: unsafe { (*server.seat).pointer_state.grab_button },
+            });
     }
     fn xdg_surface_request_resize(self: Pin<&mut Self>, event: *mut wlr_xdg_toplevel_resize_event) {
         let e = unsafe { &*(event) };
         let ctx = unsafe { self.get_unchecked_mut() };
+        if !ctx.validate_grab_serial(e.serial) {
+            return;
+        }
         let server = unsafe { &mut (*ctx.server) };
         let (index, _) = server
             .views
@@ -650,12 +2188,264 @@ impl View {
             .event_queue
             .push_back(Event::XdgToplevelRequestResize {
                 view: index,
+                serial: e.serial,
+                button: unsafe { (*server.seat).pointer_state.grab_button },
                 edges: e.edges,
             });
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Keyboard {
+    server: *mut Server,
+    device: *mut wlr_input_device,
+    keyboard: *mut wlr_keyboard,
+
+    layout_group: u32,
+
+    keyboard_key_listener: wl_listener,
+    keyboard_modifiers_listener: wl_listener,
+    device_destroy_listener: wl_listener,
+}
+
+impl Keyboard {
+    pub fn new(server: &Server, device: *mut wlr_input_device) -> Pin<Box<Keyboard>> {
+        let k = Keyboard {
+            server: server as *const _ as *mut _,
+            device,
+            keyboard: unsafe { (*device).__bindgen_anon_1.keyboard },
+
+            layout_group: 0,
+
+            keyboard_key_listener: unsafe { std::mem::zeroed() },
+            keyboard_modifiers_listener: unsafe { std::mem::zeroed() },
+            device_destroy_listener: unsafe { std::mem::zeroed() },
+        };
+        let mut k = Box::pin(k);
+
+        unsafe {
+            let ctx = k.as_mut().get_unchecked_mut();
+
+            if ctx.set_keymap(&server.keymap_config).is_err() {
+                wlr_log!(WLR_ERROR, "cannot compile keymap {:?}", server.keymap_config);
+                ctx.set_keymap(&KeymapConfig::default())
+                    .expect("cannot compile default keymap");
+            }
+            ctx.layout_group = (*ctx.keyboard).modifiers.group;
+            ctx.set_repeat_info(server.repeat_rate, server.repeat_delay);
+
+            connect_listener!(ctx, keyboard, key);
+            connect_listener!(ctx, keyboard, modifiers);
+            connect_listener!(ctx, device, destroy);
+        }
+
+        k
+    }
+    /// Compiles `config` and makes it the keymap of this keyboard.
+    pub fn set_keymap(&self, config: &KeymapConfig) -> Result<(), ServerError> {
+        unsafe {
+            let context = xkb_context_new(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                return Err(ServerError::Keymap);
+            }
+            let keymap = config.compile(context);
+            xkb_context_unref(context);
+            let keymap = keymap?;
+            wlr_keyboard_set_keymap(self.keyboard, keymap);
+            xkb_keymap_unref(keymap);
+        }
+        Ok(())
+    }
+    /// Sets the key repeat rate (in keys per second) and delay (in milliseconds).
+    /// Clients receive them through wl_keyboard.repeat_info.
+    pub fn set_repeat_info(&self, rate: i32, delay: i32) {
+        unsafe {
+            wlr_keyboard_set_repeat_info(self.keyboard, rate, delay);
+        }
+    }
+    pub fn repeat_info(&self) -> (i32, i32) {
+        unsafe {
+            (
+                (*self.keyboard).repeat_info.rate,
+                (*self.keyboard).repeat_info.delay,
+            )
+        }
+    }
+    pub fn layout_count(&self) -> u32 {
+        unsafe { xkb_keymap_num_layouts((*self.keyboard).keymap) }
+    }
+    pub fn layout_name(&self, group: u32) -> Option<String> {
+        unsafe {
+            let name = xkb_keymap_layout_get_name((*self.keyboard).keymap, group);
+            if name.is_null() {
+                None
+            } else {
+                Some(std::ffi::CStr::from_ptr(name).to_string_lossy().into_owned())
+            }
+        }
+    }
+    pub fn layout_group(&self) -> u32 {
+        unsafe { (*self.keyboard).modifiers.group }
+    }
+    pub fn set_layout_group(&self, group: u32) {
+        unsafe {
+            let m = (*self.keyboard).modifiers;
+            wlr_keyboard_notify_modifiers(self.keyboard, m.depressed, m.latched, m.locked, group);
+        }
+    }
+}
+
+implement_listener!(Keyboard, keyboard, key, wlr_event_keyboard_key);
+implement_listener!(Keyboard, keyboard, modifiers, libc::c_void);
+implement_listener!(Keyboard, device, destroy, libc::c_void);
+impl Keyboard {
+    fn keyboard_key(self: Pin<&mut Self>, event: *mut wlr_event_keyboard_key) {
+        let e = unsafe { &*(event) };
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let server = unsafe { &mut (*ctx.server) };
+        let (index, _) = server
+            .keyboards
+            .iter()
+            .find(|&(_, k)| k.as_ref().get_ref() as *const _ == ctx as *const _)
+            .expect("cant find keyboard in arena");
+        server.event_queue.push_back(Event::KeyEvent {
+            keyboard: index,
+            time_ms: e.time_msec,
+            state: e.state,
+            keycode: e.keycode,
+        });
+    }
+    fn keyboard_modifiers(self: Pin<&mut Self>, _: *mut libc::c_void) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let server = unsafe { &mut (*ctx.server) };
+        let (index, _) = server
+            .keyboards
+            .iter()
+            .find(|&(_, k)| k.as_ref().get_ref() as *const _ == ctx as *const _)
+            .expect("cant find keyboard in arena");
+        let modifiers = unsafe { (*ctx.keyboard).modifiers };
+        server.event_queue.push_back(Event::KeyModifier {
+            keyboard: index,
+            modifiers,
+        });
+        if modifiers.group != ctx.layout_group {
+            ctx.layout_group = modifiers.group;
+            server.event_queue.push_back(Event::KeyboardLayoutChange {
+                keyboard: index,
+                group: modifiers.group,
+            });
+        }
+    }
+    fn device_destroy(self: Pin<&mut Self>, _: *mut libc::c_void) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let server = unsafe { &mut (*ctx.server) };
+        let (index, _) = server
+            .keyboards
+            .iter()
+            .find(|&(_, k)| k.as_ref().get_ref() as *const _ == ctx as *const _)
+            .expect("cant find keyboard in arena");
+        let k = server
+            .keyboards
+            .remove(index)
+            .expect("cant find keyboard to remove");
+        server.dead_keyboards.push(k);
+    }
+}
+
+#[repr(C)]
+pub struct InputDevice {
+    server: *mut Server,
+    device: *mut wlr_input_device,
+
+    device_destroy_listener: wl_listener,
+}
+
+impl InputDevice {
+    pub fn new(server: &Server, device: *mut wlr_input_device) -> Pin<Box<InputDevice>> {
+        let d = InputDevice {
+            server: server as *const _ as *mut _,
+            device,
+
+            device_destroy_listener: unsafe { std::mem::zeroed() },
+        };
+        let mut d = Box::pin(d);
+
+        unsafe {
+            let ctx = d.as_mut().get_unchecked_mut();
+
+            connect_listener!(ctx, device, destroy);
+        }
+
+        d
+    }
+    pub fn device_type(&self) -> wlr_input_device_type {
+        unsafe { (*self.device).type_ }
+    }
+    pub fn info(&self) -> InputDeviceInfo {
+        unsafe {
+            let d = &*self.device;
+            let name = if d.name.is_null() {
+                String::new()
+            } else {
+                std::ffi::CStr::from_ptr(d.name).to_string_lossy().into_owned()
+            };
+            InputDeviceInfo {
+                device_type: d.type_,
+                name,
+                vendor: d.vendor,
+                product: d.product,
+            }
+        }
+    }
+}
+
+implement_listener!(InputDevice, device, destroy, libc::c_void);
+impl InputDevice {
+    fn device_destroy(self: Pin<&mut Self>, _: *mut libc::c_void) {
+        let ctx = unsafe { self.get_unchecked_mut() };
+        let server = unsafe { &mut (*ctx.server) };
+        let (index, _) = server
+            .input_devices
+            .iter()
+            .find(|&(_, d)| d.as_ref().get_ref() as *const _ == ctx as *const _)
+            .expect("cant find input device in arena");
+        let d = server
+            .input_devices
+            .remove(index)
+            .expect("cant find input device to remove");
+        unsafe {
+            match (*ctx.device).type_ {
+                wlr_input_device_type::WLR_INPUT_DEVICE_KEYBOARD => {
+                    if wlr_seat_get_keyboard(server.seat) == (*ctx.device).__bindgen_anon_1.keyboard {
+                        wlr_seat_set_keyboard(server.seat, std::ptr::null_mut());
+                    }
+                }
+                _ => {
+                    // no-op if the device was never attached
+                    wlr_cursor_detach_input_device(server.cursor, ctx.device);
+                }
+            }
+        }
+        let info = d.info();
+        server.dead_input_devices.push(d);
+        server.update_capabilities();
+
+        server.event_queue.push_back(Event::InputDeviceRemoved {
+            device: index,
+            info,
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InputDeviceInfo {
+    pub device_type: wlr_input_device_type,
+    pub name: String,
+    pub vendor: u32,
+    pub product: u32,
+}
+
+#[derive(Debug, Clone)]
 pub enum Event {
     CursorMotion {
         time_ms: u32,
@@ -680,26 +2470,93 @@ pub enum Event {
         delta_discrete: i32,
     },
     CursorFrame,
+    /// The client with pointer focus set the cursor to one of its surfaces
+    /// (or hid it). Call `Server::set_cursor_image` to override it.
+    CursorSurfaceSet {
+        hidden: bool,
+        hotspot_x: i32,
+        hotspot_y: i32,
+    },
     OutputFrame {
         output: Index,
         when: std::time::Instant,
     },
+    OutputNew {
+        output: Index,
+    },
+    OutputDestroy {
+        output: Index,
+    },
+    OutputLayoutChange,
+    OutputConfigurationApply {
+        config: Index,
+    },
+    OutputConfigurationTest {
+        config: Index,
+    },
+    FdReadable {
+        id: FdId,
+        fd: std::os::unix::io::RawFd,
+    },
+    /// The fd was hung up, or has an error if `error` is set. It is not
+    /// watched anymore, so `remove_fd` is not needed.
+    FdClosed {
+        id: FdId,
+        fd: std::os::unix::io::RawFd,
+        error: bool,
+    },
+    Timer {
+        id: TimerId,
+    },
+    Idle {
+        id: IdleId,
+    },
     KeyModifier {
         keyboard: Index,
         modifiers: wlr_keyboard_modifiers,
     },
     KeyEvent {
         keyboard: Index,
+        time_ms: u32,
         state: wlr_key_state,
         keycode: u32,
     },
+    KeyboardLayoutChange {
+        keyboard: Index,
+        group: u32,
+    },
+    InputDeviceAdded {
+        device: Index,
+        info: InputDeviceInfo,
+    },
+    InputDeviceRemoved {
+        device: Index,
+        info: InputDeviceInfo,
+    },
+    /// `button` is the one the client was clicked with
     XdgToplevelRequestMove {
         view: Index,
+        serial: u32,
+        button: u32,
     },
     XdgToplevelRequestResize {
         view: Index,
+        serial: u32,
+        button: u32,
         edges: u32,
     },
+    /// The view committed a new geometry, or acknowledged a size sent with
+    /// `View::set_size`, in which case `serial` is the one of the configure
+    ViewCommit {
+        view: Index,
+        serial: Option<u32>,
+        geometry: ViewGeometry,
+    },
+    /// A move or resize grab changed the geometry of the view
+    ViewGeometryChanged {
+        view: Index,
+        rect: Rect,
+    },
     XdgSurfaceMap {
         view: Index,
     },
@@ -714,7 +2571,217 @@ pub enum Event {
     },
 }
 
+/// xkb rules, model, layout, variant and options (RMLVO) of a keymap.
+/// Fields left to `None` take the xkbcommon defaults.
+#[derive(Debug, Clone, Default)]
+pub struct KeymapConfig {
+    pub rules: Option<String>,
+    pub model: Option<String>,
+    pub layout: Option<String>,
+    pub variant: Option<String>,
+    pub options: Option<String>,
+}
+
+impl KeymapConfig {
+    unsafe fn compile(&self, context: *mut xkb_context) -> Result<*mut xkb_keymap, ServerError> {
+        fn to_cstring(s: &Option<String>) -> Result<Option<std::ffi::CString>, ServerError> {
+            s.as_ref()
+                .map(|s| std::ffi::CString::new(s.as_str()).map_err(|_| ServerError::Keymap))
+                .transpose()
+        }
+        fn as_ptr(s: &Option<std::ffi::CString>) -> *const libc::c_char {
+            s.as_ref().map_or(std::ptr::null(), |s| s.as_ptr())
+        }
+        let rules = to_cstring(&self.rules)?;
+        let model = to_cstring(&self.model)?;
+        let layout = to_cstring(&self.layout)?;
+        let variant = to_cstring(&self.variant)?;
+        let options = to_cstring(&self.options)?;
+        let names = xkb_rule_names {
+            rules: as_ptr(&rules),
+            model: as_ptr(&model),
+            layout: as_ptr(&layout),
+            variant: as_ptr(&variant),
+            options: as_ptr(&options),
+        };
+        let keymap = xkb_keymap_new_from_names(
+            context,
+            &names as *const _,
+            xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+        );
+        if keymap.is_null() {
+            return Err(ServerError::Keymap);
+        }
+        Ok(keymap)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode {
+    pub width: i32,
+    pub height: i32,
+    /// Refresh rate in mHz
+    pub refresh: i32,
+    pub preferred: bool,
+}
+
+impl Mode {
+    fn from_wlr(mode: &wlr_output_mode) -> Mode {
+        Mode {
+            width: mode.width,
+            height: mode.height,
+            refresh: mode.refresh,
+            preferred: mode.flags & wl_output::Mode::Preferred.to_raw() != 0,
+        }
+    }
+}
+
+/// The default output mode policy: the preferred mode, or the first one
+/// if the output doesn't flag any.
+pub fn preferred_mode(output: &Output) -> Option<Mode> {
+    output
+        .modes()
+        .find(|m| m.preferred)
+        .or_else(|| output.modes().next())
+}
+
+/// The state of one output in an output configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputHeadConfig {
+    pub output: Index,
+    pub enabled: bool,
+    /// One of the modes advertised by the output
+    pub mode: Option<Mode>,
+    /// Used when `mode` is `None`
+    pub custom_mode: Option<Mode>,
+    pub x: i32,
+    pub y: i32,
+    pub transform: wl_output_transform,
+    pub scale: f32,
+}
+
+pub type FdId = Index;
+pub type TimerId = Index;
+pub type IdleId = Index;
+
+/// One more than the highest signal number, realtime signals included
+const NSIG: libc::c_int = 65;
+
+const WL_EVENT_READABLE: u32 = 0x01;
+const WL_EVENT_HANGUP: u32 = 0x04;
+const WL_EVENT_ERROR: u32 = 0x08;
+
+struct FdSource {
+    server: *mut Server,
+    source: *mut wayland_sys::server::wl_event_source,
+    /// The fd passed to `add_fd`: the event loop watches a dup of it
+    fd: std::os::unix::io::RawFd,
+}
+
+impl FdSource {
+    unsafe extern "C" fn callback(_fd: libc::c_int, mask: u32, data: *mut libc::c_void) -> libc::c_int {
+        let f = &*(data as *const FdSource);
+        let fd = f.fd;
+        let server = &mut (*f.server);
+        let (index, _) = server
+            .fd_sources
+            .iter()
+            .find(|&(_, o)| o.as_ref().get_ref() as *const _ == f as *const _)
+            .expect("cant find fd source in arena");
+        if mask & WL_EVENT_READABLE != 0 {
+            server
+                .event_queue
+                .push_back(Event::FdReadable { id: index, fd });
+        }
+        if mask & (WL_EVENT_HANGUP | WL_EVENT_ERROR) != 0 {
+            // The fd would be reported again on every dispatch, stop watching it
+            let f = server
+                .fd_sources
+                .remove(index)
+                .expect("cant find fd source to remove");
+            ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_source_remove, f.source);
+            server.dead_fd_sources.push(f);
+            server.event_queue.push_back(Event::FdClosed {
+                id: index,
+                fd,
+                error: mask & WL_EVENT_ERROR != 0,
+            });
+        }
+        0
+    }
+}
+
+struct Timer {
+    server: *mut Server,
+    source: *mut wayland_sys::server::wl_event_source,
+}
+
+impl Timer {
+    unsafe extern "C" fn callback(data: *mut libc::c_void) -> libc::c_int {
+        let t = &*(data as *const Timer);
+        let server = &mut (*t.server);
+        let (index, _) = server
+            .timers
+            .iter()
+            .find(|&(_, o)| o.as_ref().get_ref() as *const _ == t as *const _)
+            .expect("cant find timer in arena");
+        server.event_queue.push_back(Event::Timer { id: index });
+        0
+    }
+}
+
+struct Idle {
+    server: *mut Server,
+    source: *mut wayland_sys::server::wl_event_source,
+}
+
+impl Idle {
+    unsafe extern "C" fn callback(data: *mut libc::c_void) {
+        let i = &*(data as *const Idle);
+        let server = &mut (*i.server);
+        let (index, _) = server
+            .idles
+            .iter()
+            .find(|&(_, o)| o.as_ref().get_ref() as *const _ == i as *const _)
+            .expect("cant find idle in arena");
+        // The event loop frees idle sources after running them
+        let i = server
+            .idles
+            .remove(index)
+            .expect("cant find idle to remove");
+        server.dead_idles.push(i);
+        server.event_queue.push_back(Event::Idle { id: index });
+    }
+}
+
+/// wl_event_source_timer_update takes milliseconds, and 0 disarms the timer
+fn duration_to_timer_ms(d: std::time::Duration) -> libc::c_int {
+    d.as_millis().max(1).min(libc::c_int::max_value() as u128) as libc::c_int
+}
+
+struct PendingOutputConfig {
+    config: *mut wlr_output_configuration_v1,
+    test: bool,
+}
+
 #[derive(Clone, Copy)]
+enum GrabMode {
+    Move,
+    Resize(u32),
+}
+
+/// An interactive move or resize, following the cursor
+struct Grab {
+    view: Index,
+    /// The grab ends when this button is released
+    button: u32,
+    mode: GrabMode,
+    cursor_x: f64,
+    cursor_y: f64,
+    rect: Rect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Rect {
     pub x: i32,
     pub y: i32,
@@ -728,6 +2795,17 @@ impl Rect {
     }
 }
 
+/// Committed geometry of a view, relative to its main surface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViewGeometry {
+    /// The window geometry set by the client, excluding decorations
+    /// drawn by the client such as shadows
+    pub window: Rect,
+    /// The area covered by the surface and its subsurfaces
+    pub extents: Rect,
+    pub scale: i32,
+}
+
 pub struct Surface<'a> {
     surface: *mut wlr_surface,
     _lifetime: std::marker::PhantomData<&'a View>,
@@ -737,3 +2815,135 @@ pub struct SurfaceHit<'a> {
     pub hy: f64,
     pub surface: Surface<'a>,
 }
+
+fn c_chars_to_string(chars: &[libc::c_char]) -> String {
+    let bytes: Vec<u8> = chars
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(serials: &[u32]) -> VecDeque<PendingSize> {
+        serials
+            .iter()
+            .map(|&serial| PendingSize {
+                serial,
+                width: serial,
+                height: serial,
+            })
+            .collect()
+    }
+
+    /// A headless server with two outputs, with its socket in a runtime dir
+    /// of its own.
+    fn headless_server() -> Pin<Box<Server>> {
+        let dir = std::env::temp_dir().join(format!("ynwm-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("cannot create runtime dir");
+        std::env::set_var("XDG_RUNTIME_DIR", &dir);
+        ServerBuilder::new()
+            .backend(BackendKind::Headless)
+            .headless_outputs(2, 800, 600)
+            .build()
+            .expect("cannot create headless server")
+    }
+
+    #[test]
+    fn headless_output_configuration() {
+        let mut server = headless_server();
+        let ctx = unsafe { server.as_mut().get_unchecked_mut() };
+        let outputs: Vec<_> = ctx.outputs.iter().map(|(idx, _)| idx).collect();
+        assert_eq!(outputs.len(), 2);
+        let current = |ctx: &Server| -> Vec<OutputHeadConfig> {
+            outputs.iter().map(|&o| ctx.output_head_config(o)).collect()
+        };
+        let before = current(ctx);
+
+        let mut heads = before.clone();
+        heads[1].x = 2000;
+        heads[1].y = 0;
+        heads[1].scale = 2.;
+        // testing doesn't change anything
+        assert!(ctx.apply_output_configuration(&heads, true));
+        assert_eq!(current(ctx), before);
+
+        assert!(ctx.apply_output_configuration(&heads, false));
+        assert_eq!(current(ctx), heads);
+
+        // the second head can't be applied, so the first one isn't either
+        let mut bad = heads.clone();
+        bad[0].x = 5000;
+        bad[1].mode = Some(Mode {
+            width: 1,
+            height: 1,
+            refresh: 1,
+            preferred: false,
+        });
+        assert!(!ctx.apply_output_configuration(&bad, true));
+        assert!(!ctx.apply_output_configuration(&bad, false));
+        assert_eq!(current(ctx), heads);
+
+        let duplicated = vec![heads[0].clone(), heads[0].clone()];
+        assert!(!ctx.apply_output_configuration(&duplicated, false));
+
+        let disabled = vec![OutputHeadConfig {
+            enabled: false,
+            ..heads[1].clone()
+        }];
+        assert!(ctx.apply_output_configuration(&disabled, false));
+        assert!(!current(ctx)[1].enabled);
+        assert!(current(ctx)[0].enabled);
+
+        // a configuration that was already answered is not applied again
+        let stale = ctx.output_configs.insert(PendingOutputConfig {
+            config: std::ptr::null_mut(),
+            test: false,
+        });
+        ctx.output_configs.remove(stale);
+        assert!(!server.as_mut().accept_output_configuration(stale));
+    }
+
+    #[test]
+    fn acked_size_exact_serial() {
+        let mut p = pending(&[3, 5, 8]);
+        assert_eq!(take_acked_size(&mut p, 5).map(|s| s.serial), Some(5));
+        assert_eq!(p, pending(&[8]));
+    }
+
+    #[test]
+    fn acked_size_newer_serial() {
+        // a later configure, e.g. from set_activated, acks the sizes before it
+        let mut p = pending(&[3, 5]);
+        assert_eq!(take_acked_size(&mut p, 6).map(|s| s.serial), Some(5));
+        assert!(p.is_empty());
+    }
+
+    #[test]
+    fn acked_size_older_serial() {
+        let mut p = pending(&[3, 5]);
+        assert_eq!(take_acked_size(&mut p, 2), None);
+        assert_eq!(p, pending(&[3, 5]));
+    }
+
+    #[test]
+    fn cancelled_configure_size() {
+        // sizes set before the configure is sent share its serial
+        let mut p = pending(&[3, 5, 8, 8]);
+        discard_unsent_sizes(&mut p, 8);
+        assert_eq!(p, pending(&[3, 5]));
+        assert_eq!(take_acked_size(&mut p, 8).map(|s| s.serial), Some(5));
+        assert!(p.is_empty());
+    }
+
+    #[test]
+    fn acked_size_wrapping_serial() {
+        let mut p = pending(&[u32::max_value() - 1, 1, 4]);
+        assert_eq!(take_acked_size(&mut p, 2).map(|s| s.serial), Some(1));
+        assert_eq!(p, pending(&[4]));
+    }
+}
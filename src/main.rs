@@ -1,30 +1,63 @@
 use generational_arena::Index;
+use wlroots_sys::{wlr_button_state, wlr_edges, wlr_keyboard_modifier};
 use ynwm::*;
 
 struct ViewData {
     view: Index,
-    rect: Rect,
     mapped: bool,
 }
-enum CursorMode {
-    Move,
-    Resize,
-    Passthrough,
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+
+/// Where the main surface of a view goes in layout coordinates: the view
+/// position is the one of its window geometry, which CSD shadows make start
+/// inside of the surface
+fn surface_rect(view: &View) -> Rect {
+    let (x, y) = view.position();
+    let window = view.get_rect();
+    Rect {
+        x: x - window.x,
+        y: y - window.y,
+        ..window
+    }
 }
 fn view_at<'a>(server: &'a Server, views: &[ViewData], x: i32, y: i32) -> Option<(Index, SurfaceHit<'a>)> {
     views.iter().rev().find_map(|v| {
         let view = server.get_view(v.view);
-        let rel_x = (x - v.rect.x) as f64;
-        let rel_y = (y - v.rect.y) as f64;
+        let r = surface_rect(view);
+        let rel_x = (x - r.x) as f64;
+        let rel_y = (y - r.y) as f64;
         view.surface_at(rel_x, rel_y).map(|sh| {
             (v.view, sh)
         })
     })
 }
+fn focus_view(server: &Server, views: &mut Vec<ViewData>, focused: &mut Option<Index>, view: Index) {
+    if *focused == Some(view) {
+        return;
+    }
+    if let Some(prev) = focused.take() {
+        server.get_view(prev).set_activated(false);
+    }
+    // raise the view on top of the others
+    let idx = views
+        .iter()
+        .position(|i| i.view == view)
+        .expect("view not found");
+    let v = views.remove(idx);
+    views.push(v);
+
+    server.get_view(view).set_activated(true);
+    server.keyboard_notify_enter_view(view);
+    *focused = Some(view);
+}
 fn main() {
     let mut ctx = ynwm::Server::new().expect("failed to create context");
     let mut views = Vec::new();
-    let mut cursor_mode = CursorMode::Passthrough;
+    let mut focused = None;
+    let mut modifiers = 0;
+    // buttons whose press started a grab instead of going to the client
+    let mut withheld_buttons: Vec<u32> = Vec::new();
     loop {
         for e in ctx.as_mut().poll_events() {
             match e {
@@ -44,42 +77,100 @@ fn main() {
                         }
                         _ => unreachable!(),
                     };
-                    match cursor_mode {
-                        CursorMode::Passthrough => {
-                            let (x, y) = ctx.as_ref().get_cursor();
-                            if let Some((view, hit)) = view_at(&ctx, &views, x as i32, y as i32) {
-                                ctx.pointer_notify_enter(&hit.surface, hit.hx, hit.hy);
-                                ctx.pointer_notify_motion(time_ms, hit.hx, hit.hy);
-                                ctx.as_mut().set_cursor_image("right_ptr");
-                            } else {
-                                ctx.pointer_clear_focus();
-                                ctx.as_mut().set_cursor_image("left_ptr");
-                            }
-                        }
-                        CursorMode::Move => {
-                        }
-                        CursorMode::Resize => {
+                    if ctx.grabbed_view().is_none() {
+                        let (x, y) = ctx.as_ref().get_cursor();
+                        if let Some((_, hit)) = view_at(&ctx, &views, x as i32, y as i32) {
+                            ctx.pointer_notify_enter(&hit.surface, hit.hx, hit.hy);
+                            ctx.pointer_notify_motion(time_ms, hit.hx, hit.hy);
+                        } else {
+                            ctx.pointer_clear_focus();
+                            ctx.as_mut().set_cursor_image("left_ptr");
                         }
                     }
                 },
                 Event::CursorFrame => {
                     ctx.pointer_notify_frame();
                 },
+                Event::CursorButton {
+                    time_ms,
+                    state,
+                    button,
+                } => {
+                    if state == wlr_button_state::WLR_BUTTON_PRESSED {
+                        let (x, y) = ctx.get_cursor();
+                        if let Some((view, _)) = view_at(&ctx, &views, x as i32, y as i32) {
+                            focus_view(&ctx, &mut views, &mut focused, view);
+                            if modifiers & wlr_keyboard_modifier::WLR_MODIFIER_ALT as u32 != 0 {
+                                match button {
+                                    BTN_LEFT => ctx.as_mut().begin_move(view, button),
+                                    BTN_RIGHT => ctx.as_mut().begin_resize(
+                                        view,
+                                        button,
+                                        wlr_edges::WLR_EDGE_BOTTOM as u32
+                                            | wlr_edges::WLR_EDGE_RIGHT as u32,
+                                    ),
+                                    _ => {}
+                                }
+                                // the click belongs to the grab, not to the client
+                                if ctx.grabbed_view().is_some() {
+                                    withheld_buttons.push(button);
+                                    continue;
+                                }
+                            }
+                        }
+                    } else if let Some(i) = withheld_buttons.iter().position(|&b| b == button) {
+                        // the client didn't get the press, so it doesn't get the release
+                        withheld_buttons.remove(i);
+                        continue;
+                    }
+                    ctx.pointer_notify_button(time_ms, button, state);
+                }
+                Event::CursorAxis {
+                    time_ms,
+                    orientation,
+                    source,
+                    delta,
+                    delta_discrete,
+                } => {
+                    ctx.pointer_notify_axis(time_ms, orientation, delta, delta_discrete, source);
+                }
+                Event::KeyModifier {
+                    keyboard,
+                    modifiers: m,
+                } => {
+                    modifiers = m.depressed;
+                    if ctx.keyboard_set_active(keyboard) {
+                        ctx.keyboard_notify_modifiers(&m);
+                    }
+                }
+                Event::KeyEvent {
+                    keyboard,
+                    time_ms,
+                    state,
+                    keycode,
+                } => {
+                    if ctx.keyboard_set_active(keyboard) {
+                        ctx.keyboard_notify_key(time_ms, keycode, state);
+                    }
+                }
                 Event::XdgSurfaceNew { view } => {
-                    let v = ctx.get_view(view);
-                    let rect = v.get_rect();
                     views.push(ViewData {
                         view,
-                        rect,
                         mapped: false,
                     });
                 }
+                Event::ViewCommit { .. } | Event::ViewGeometryChanged { .. } => {
+                    // the geometry is read from the views when rendering
+                }
                 Event::XdgSurfaceDestroy { view } => {
                     let idx = views
                         .iter()
                         .position(|i| i.view == view)
                         .expect("view not found");
                     views.remove(idx);
+                    if focused == Some(view) {
+                        focused = None;
+                    }
                 }
                 Event::XdgSurfaceMap { view } => {
                     let idx = views
@@ -94,17 +185,39 @@ fn main() {
                         .position(|i| i.view == view)
                         .expect("view not found");
                     views[idx].mapped = false;
+                    if focused == Some(view) {
+                        ctx.keyboard_clear_focus();
+                        focused = None;
+                    }
+                }
+                Event::XdgToplevelRequestMove { view, button, .. } => {
+                    ctx.as_mut().begin_move(view, button);
+                }
+                Event::XdgToplevelRequestResize {
+                    view, button, edges, ..
+                } => {
+                    ctx.as_mut().begin_resize(view, button, edges);
                 }
                 Event::OutputFrame { output, when } => {
-                    let output = ctx.as_mut().get_output_mut(output);
-                    let to_render = views.iter().filter_map(|v| {
-                        if !v.mapped {
-                            None
-                        } else {
-                            Some((v.view, v.rect))
-                        }
-                    });
-                    output.render_views(to_render);
+                    // frames queued before the output was destroyed
+                    let to_render: Vec<_> = views
+                        .iter()
+                        .filter(|v| v.mapped)
+                        .map(|v| (v.view, surface_rect(ctx.get_view(v.view))))
+                        .collect();
+                    let output = match ctx.as_mut().try_get_output_mut(output) {
+                        Some(output) => output,
+                        None => continue,
+                    };
+                    output.render_views(to_render.into_iter());
+                }
+                Event::OutputDestroy { output } => {
+                    // nothing is kept per output, the library removed it from the layout
+                    println!("output {:?} destroyed", output);
+                }
+                Event::OutputConfigurationApply { config }
+                | Event::OutputConfigurationTest { config } => {
+                    ctx.as_mut().accept_output_configuration(config);
                 }
                 _ => {
                     println!("{:?}", e);
@@ -0,0 +1,70 @@
+use crate::{Event, Server};
+
+use async_io::Async;
+use futures_core::Stream;
+
+use std::collections::VecDeque;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// The event loop fd, owned by the wayland display: it's not closed on drop.
+struct EventLoopFd(RawFd);
+
+impl AsRawFd for EventLoopFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// A `Stream` of the events of a `Server`, woken up by its event loop fd.
+/// It works with any executor: the fd is polled by the async-io reactor.
+///
+/// The stream borrows the server, use `server()` to get it back while
+/// handling an event.
+pub struct EventStream<'a> {
+    server: Pin<&'a mut Server>,
+    fd: Async<EventLoopFd>,
+    pending: VecDeque<Event>,
+}
+
+impl<'a> EventStream<'a> {
+    pub fn new(server: Pin<&'a mut Server>) -> std::io::Result<EventStream<'a>> {
+        let fd = Async::new(EventLoopFd(server.event_loop_fd()))?;
+        Ok(EventStream {
+            server,
+            fd,
+            pending: VecDeque::new(),
+        })
+    }
+    pub fn server(&mut self) -> Pin<&mut Server> {
+        self.server.as_mut()
+    }
+}
+
+impl<'a> Stream for EventStream<'a> {
+    type Item = Event;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Event>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(e) = this.pending.pop_front() {
+                return Poll::Ready(Some(e));
+            }
+            this.pending
+                .extend(this.server.as_mut().dispatch(Some(Duration::from_millis(0))));
+            if !this.pending.is_empty() {
+                continue;
+            }
+            // `dispatch` flushes before dispatching: send the replies it
+            // just queued before going to sleep
+            this.server.flush_clients();
+            match this.fd.poll_readable(cx) {
+                Poll::Ready(Ok(())) => continue,
+                Poll::Ready(Err(_)) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}